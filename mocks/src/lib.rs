@@ -0,0 +1,157 @@
+//! Contracts which play the part of other parties in the integration tests of
+//! the Dex.
+//!
+//! - `HostileReceiver` calls back into a contract whenever it receives CIS2
+//!   tokens, and records how the call back went.
+//! - `MockOracle` serves price feeds through `getPrice`, set with `setPrice`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use concordium_std::*;
+
+/// How the call back of `HostileReceiver` went.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub enum CallbackResult {
+    Succeeded,
+    /// The called contract rejected, with the given reason and return value.
+    Rejected {
+        reason:       i32,
+        return_value: Vec<u8>,
+    },
+    /// The call failed for another reason, such as a missing entrypoint.
+    Failed,
+}
+
+/// Parameters for the `init` method of `HostileReceiver`, describing the call
+/// back.
+#[derive(Serialize, SchemaType)]
+pub struct HostileReceiverParams {
+    pub target:     ContractAddress,
+    pub entrypoint: OwnedEntrypointName,
+    pub parameter:  OwnedParameter,
+}
+
+#[derive(Serialize, SchemaType)]
+pub struct HostileReceiverState {
+    pub callback: HostileReceiverParams,
+    /// How the last call back went, `None` until tokens are received.
+    pub result:   Option<CallbackResult>,
+}
+
+#[init(contract = "HostileReceiver", parameter = "HostileReceiverParams")]
+fn hostile_receiver_init(
+    ctx: &InitContext,
+    _state_builder: &mut StateBuilder,
+) -> InitResult<HostileReceiverState> {
+    Ok(HostileReceiverState {
+        callback: ctx.parameter_cursor().get()?,
+        result:   None,
+    })
+}
+
+/// Accepts any CIS2 tokens, after making the call back.
+#[receive(contract = "HostileReceiver", name = "onReceivingCIS2", mutable)]
+fn hostile_receiver_on_receiving_cis2(
+    _ctx: &ReceiveContext,
+    host: &mut Host<HostileReceiverState>,
+) -> ReceiveResult<()> {
+    let callback = &host.state().callback;
+    let (target, entrypoint, parameter) =
+        (callback.target, callback.entrypoint.clone(), callback.parameter.clone());
+    let response = host.invoke_contract_raw(
+        &target,
+        parameter.as_parameter(),
+        entrypoint.as_entrypoint_name(),
+        Amount::zero(),
+    );
+    let result = match response {
+        Ok(_) => CallbackResult::Succeeded,
+        Err(CallContractError::LogicReject {
+            reason,
+            mut return_value,
+        }) => {
+            let mut bytes = vec![0; return_value.size() as usize];
+            return_value.read_exact(&mut bytes)?;
+            CallbackResult::Rejected {
+                reason,
+                return_value: bytes,
+            }
+        }
+        Err(_) => CallbackResult::Failed,
+    };
+    host.state_mut().result = Some(result);
+    Ok(())
+}
+
+/// Returns how the last call back went.
+#[receive(
+    contract = "HostileReceiver",
+    name = "result",
+    return_value = "Option<CallbackResult>"
+)]
+fn hostile_receiver_result(
+    _ctx: &ReceiveContext,
+    host: &Host<HostileReceiverState>,
+) -> ReceiveResult<Option<CallbackResult>> {
+    Ok(host.state().result.clone())
+}
+
+/// A price of a feed of `MockOracle`, and when it was set.
+#[derive(Serialize, SchemaType, Clone, Copy)]
+pub struct FeedPrice {
+    pub price:      Amount,
+    pub updated_at: Timestamp,
+}
+
+/// Parameters for the `setPrice` method of `MockOracle`.
+#[derive(Serialize, SchemaType)]
+pub struct SetPriceParams {
+    pub feed_id: u64,
+    pub price:   Amount,
+}
+
+#[derive(Serial, DeserialWithState)]
+#[concordium(state_parameter = "S")]
+pub struct MockOracleState<S = StateApi> {
+    feeds: StateMap<u64, FeedPrice, S>,
+}
+
+#[init(contract = "MockOracle")]
+fn mock_oracle_init(
+    _ctx: &InitContext,
+    state_builder: &mut StateBuilder,
+) -> InitResult<MockOracleState> {
+    Ok(MockOracleState {
+        feeds: state_builder.new_map(),
+    })
+}
+
+/// Sets the price of a feed, as updated at the current block time.
+#[receive(contract = "MockOracle", name = "setPrice", parameter = "SetPriceParams", mutable)]
+fn mock_oracle_set_price(
+    ctx: &ReceiveContext,
+    host: &mut Host<MockOracleState>,
+) -> ReceiveResult<()> {
+    let params: SetPriceParams = ctx.parameter_cursor().get()?;
+    let _ = host.state_mut().feeds.insert(params.feed_id, FeedPrice {
+        price:      params.price,
+        updated_at: ctx.metadata().slot_time(),
+    });
+    Ok(())
+}
+
+/// Returns the price of the feed with the given id. Rejects if it was never
+/// set.
+#[receive(
+    contract = "MockOracle",
+    name = "getPrice",
+    parameter = "u64",
+    return_value = "FeedPrice"
+)]
+fn mock_oracle_get_price(
+    ctx: &ReceiveContext,
+    host: &Host<MockOracleState>,
+) -> ReceiveResult<FeedPrice> {
+    let feed_id: u64 = ctx.parameter_cursor().get()?;
+    let feed = host.state().feeds.get(&feed_id).ok_or(Reject::default())?;
+    Ok(*feed)
+}
@@ -3,7 +3,9 @@
 
 use concordium_std::*;
 
-#[derive(Debug, Reject, Serial, SchemaType)]
+use crate::state::IneligibleReason;
+
+#[derive(Debug, Reject, Serial, SchemaType, PartialEq, Eq)]
 pub enum DexError {
     ParseParams,
     CalledByAContract,
@@ -16,4 +18,76 @@ pub enum DexError {
     NoBalance,
     NotOperator,
     InvalidTokenQuantity,
-}
\ No newline at end of file
+    InvalidAuctionParams,
+    AuctionNotFound,
+    AuctionNotActive,
+    AuctionNotEnded,
+    BidTooLow,
+    InvalidPriceMode,
+    InvalidExpiry,
+    SellerBalanceGone,
+    OperatorRevoked,
+    LogFull,
+    LogMalformed,
+    Reentrancy,
+    NoProceeds,
+    Unauthorized,
+    BuybackLimitExceeded,
+    CollectionNotApproved,
+    PriceOverflow,
+    InvalidRoyalty,
+    InvalidFees,
+    InvalidReferrer,
+    Paused,
+    GovernanceNotConfigured,
+    ProposalNotFound,
+    VotingClosed,
+    AlreadyVoted,
+    NoVotingPower,
+    TimelockNotElapsed,
+    ProposalNotPassed,
+    ProposalExecuted,
+    InvalidGovernance,
+    MultisigNotConfigured,
+    AdminActionNotFound,
+    AlreadyApproved,
+    InvalidAdmins,
+    UpgradeFailed,
+    InvalidAdminDelay,
+    AttestationRequired,
+    UnknownVerifier,
+    InvalidAttestation,
+    PolicyNotMet,
+    Blocked,
+    MarketHalted,
+    InvalidPriceWindow,
+    PriceHistoryTooShort,
+    OracleError,
+    StalePrice,
+    InvalidSignature,
+    OrderExpired,
+    OrderCancelled,
+    InvalidNonce,
+}
+
+impl From<LogError> for DexError {
+    fn from(le: LogError) -> Self {
+        match le {
+            LogError::Full => Self::LogFull,
+            LogError::Malformed => Self::LogMalformed,
+        }
+    }
+}
+
+impl From<IneligibleReason> for DexError {
+    fn from(reason: IneligibleReason) -> Self {
+        match reason {
+            IneligibleReason::NotAttested | IneligibleReason::Revoked => Self::AttestationRequired,
+            IneligibleReason::UnknownVerifier => Self::UnknownVerifier,
+            IneligibleReason::Expired => Self::InvalidAttestation,
+            IneligibleReason::BelowMinimumAge
+            | IneligibleReason::ResidenceNotAllowed
+            | IneligibleReason::NationalityNotAllowed => Self::PolicyNotMet,
+        }
+    }
+}
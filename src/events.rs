@@ -0,0 +1,45 @@
+//! Provides the events which are logged by Marketplace Contract.
+
+use concordium_std::*;
+
+use crate::ContractTokenId;
+
+/// Why a listing was removed from the marketplace.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub enum DelistReason {
+    /// The seller no longer holds the listed quantity.
+    SellerBalanceGone,
+    /// The seller no longer has the Dex as an operator.
+    OperatorRevoked,
+}
+
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq)]
+pub struct ListingRemovedEvent {
+    pub token_id: ContractTokenId,
+    pub contract: ContractAddress,
+    pub owner: Address,
+    pub reason: DelistReason,
+}
+
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq)]
+pub struct BlocklistEvent {
+    pub address: Address,
+}
+
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq)]
+pub struct MarketHaltedEvent {
+    pub token_id: ContractTokenId,
+    pub contract: ContractAddress,
+    /// CCD price per unit of the trade which tripped the circuit breaker.
+    pub price: Amount,
+    pub reference_price: Amount,
+    pub halted_until: Timestamp,
+}
+
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq)]
+pub enum DexEvent {
+    ListingRemoved(ListingRemovedEvent),
+    Blocked(BlocklistEvent),
+    Unblocked(BlocklistEvent),
+    MarketHalted(MarketHaltedEvent),
+}
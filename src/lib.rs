@@ -5,36 +5,125 @@
 //! - `add` : adds the token to the list of buyable tokens taking the price of
 //!   the token as input.
 //! - `transfer` : transfer the authority of the input listed token from one
-//!   address to another.
+//!   address to another. Listings are sold at a fixed price or, in Dutch
+//!   auction mode, at a price decaying over time.
+//! - `quote` : returns the current CCD price of a listing. Listings can be
+//!   priced in CCD or in euro, converted at the chain's exchange rate, and
+//!   can follow the price feed of an oracle contract.
+//! - `pruneExpired` : removes expired listings in bounded batches.
+//! - `delistStale` : removes a listing the seller no longer backs with tokens
+//!   or operator rights.
+//! - `withdraw` : pays out the proceeds credited to a seller by sales, and
+//!   what buyers paid over the price of their purchase.
+//! - `setContractPrincipals`, `withdrawToContract` : lets smart-contract
+//!   wallets list tokens and receive bought tokens through their CIS2 receive
+//!   hook. Age verification is done off-chain against the identity behind an
+//!   account, which a contract does not have, so contracts are rejected
+//!   unless the admins turn this on.
+//! - `depositReserve`, `withdrawReserve`, `setBuybackLimits` : owner managed
+//!   CCD reserve and daily caps for buying tokens through `transfer_cis2`.
+//! - `registerCollection`, `removeCollection`, `setPermissionless` : owner
+//!   managed registry of CIS2 collections approved for trading.
+//! - `setFees`, `referralStats` : marketplace commission, a share of which is
+//!   credited to the referrer of a trade.
+//! - `setPaused` : stops listing, buying and auctioning of tokens.
+//! - `setGovernance`, `propose`, `vote`, `executeProposal` : hands the
+//!   marketplace parameters over to `techFiestaToken` holders, who vote on
+//!   proposals with their balance at a snapshot. Passed proposals are applied
+//!   after a timelock.
+//! - `setAdmins`, `proposeAdminAction`, `approveAdminAction` : M-of-N
+//!   multisig control of the privileged entrypoints and of upgrades.
+//! - `executeAdminAction`, `cancelAdminAction`, `viewAdminQueue`,
+//!   `setAdminDelay` : admin changes are queued, and can be cancelled, until
+//!   the admin delay has passed. Only pausing trading takes effect at once.
+//! - `setIdentityPolicy`, `setCollectionPolicy`, `setTokenPolicy`,
+//!   `setVerifier` : identity claims, such as a minimum age or allowed
+//!   countries of residence, which traders prove with claims signed by a
+//!   trusted verifier. Collection owners can require claims on top of the
+//!   marketplace policy, for their whole collection or for single tokens.
+//! - `postAttestation`, `revokeAttestation`, `checkEligibility` : registry of
+//!   identity claims posted by verifiers, used for trades which do not carry
+//!   signed claims, so that claims are verified once instead of on every trade.
+//! - `setCompliance`, `block`, `unblock`, `isBlocked` : blocklist of
+//!   addresses flagged by compliance, which cannot list or trade tokens here.
+//!   `techFiestaToken` queries it on every transfer and mint.
+//! - `setCircuitBreaker`, `viewMarket` : halts trading in a market for a
+//!   cooldown once a trade moves its price too far from the reference price.
+//! - `consult` : time-weighted average price of a market over a window, from
+//!   the prices of its latest trades, for use by other contracts.
+//! - `fillOrder`, `cancelOrders`, `orderHash`, `viewOrderFill`, `minNonce` :
+//!   sell orders signed off-chain, so that sellers pay nothing until a buyer
+//!   fills them. Sellers cancel orders by raising their minimum nonce.
+//! - `setRoyalty`, `viewRoyaltyChange` : lets the owner of a registered
+//!   collection set the royalty paid to its creator on every sale, up to 10%.
+//!   Raises apply after the admin delay.
+//! - `createAuction`, `bid`, `settleAuction` : English auctions with a reserve
+//!   price, where late bids extend the end of the auction.
 //!
-mod errors;
-mod parameter;
-mod state;
+pub mod errors;
+pub mod events;
+pub mod parameter;
+pub mod state;
 
 use concordium_cis2::*;
 use concordium_std::*;
 use errors::DexError;
-use parameter::{AddParams, InitParams, TokenList};
-use state::{ State, TokenInfo, TokenListItem,};
+use events::{BlocklistEvent, DelistReason, DexEvent, ListingRemovedEvent, MarketHaltedEvent};
+use parameter::{
+    AddParams, AdminQueue, BalanceOfAtQuery, BalanceOfAtQueryParams, BidParams, CollectionRegistry,
+    ConsultParams, CreateAuctionParams, EligibilityQuery, FillOrderParams, InitParams,
+    ListingParams, MarketParams, OraclePrice, Order, PruneParams, Quote, QuoteParams,
+    RegisterCollectionParams, SetCollectionPolicyParams, SetRoyaltyParams, SetTokenPolicyParams,
+    SetVerifierParams, SignedAttestation, TokenList, VoteParams,
+};
+use state::{
+    adjust_price, canonical, canonical_address, euro_to_ccd, AdminAction, AdminProposal,
+    AttestationRecord, AuctionState, Bid, BuybackCapacity, BuybackLimits, CircuitBreakerConfig,
+    Currency, DexAction, FeeConfig, GovernanceConfig, IdentityClaims, IdentityPolicy,
+    IneligibleReason, MarketBreaker, MultisigConfig, PriceMode, Proposal, PruneResult,
+    QueuedAdminAction, ReferralStats, Royalty, RoyaltyChange, State, TokenInfo, TokenListItem,
+    TokenPriceState,
+};
 
 use crate::{parameter::TransferParams, state::TokenOwnerInfo};
 
 type ContractResult<A> = Result<A, DexError>;
 
 /// Type of token Id used by the CIS2 contract.
-type ContractTokenId = TokenIdU8;
+///
+/// CIS2 serializes every token id as a length prefixed byte string, so a
+/// `TokenIdVec` holding the serialized bytes of a `TokenIdU8`, `TokenIdU32` or
+/// any other token id is encoded exactly as the contract expects it.
+pub type ContractTokenId = TokenIdVec;
 
 /// Type of Token Amount used by the CIS2 contract.
-type ContractTokenAmount = TokenAmountU64;
+///
+/// CIS2 serializes every token amount in LEB128, so a `TokenAmountU256` is
+/// encoded exactly as any smaller token amount type. A contract with a smaller
+/// amount type rejects amounts out of its range.
+pub type ContractTokenAmount = TokenAmountU256;
 
 /// Type of state.
 type ContractState<S> = State<S, ContractTokenId, ContractTokenAmount>;
+type ContractAuctionState = AuctionState<ContractTokenId, ContractTokenAmount>;
+type ContractGovernanceConfig = GovernanceConfig<ContractTokenId, ContractTokenAmount>;
+type ContractProposal = Proposal<ContractTokenAmount>;
+type ContractAdminAction = AdminAction<ContractTokenId, ContractTokenAmount>;
+type ContractAdminProposal = AdminProposal<ContractTokenId, ContractTokenAmount>;
 type Cis2ClientResult<T> = Result<T, concordium_cis2::Cis2ClientError<()>>;
 
+/// A bid placed this close to the end of an auction extends the auction, so
+/// that it ends this long after the bid.
+const AUCTION_EXTENSION: Duration = Duration::from_minutes(10);
+
+/// Largest royalty a collection owner can set, in basis points.
+const MAX_ROYALTY_BASIS_POINTS: u16 = 1_000;
+
 /// Initializes a new Exchange Contract
 ///
-/// This function can be called by using InitParams.
-#[init(contract = "RagnarDEX", parameter = "InitParams")]
+/// This function can be called by using InitParams. The collections in the
+/// parameter are registered at once, without waiting for the admin delay.
+#[init(contract = "RagnarDEX", parameter = "InitParams", event = "DexEvent")]
 fn init<S: HasStateApi>(
     ctx: &impl HasInitContext,
     state_builder: &mut StateBuilder<S>,
@@ -49,52 +138,99 @@ fn init<S: HasStateApi>(
         DexError::InvalidTokenQuantity.into()
     );
 
-    Ok(State::new(state_builder))
+    let mut state = State::new(state_builder);
+    for collection in params.collections {
+        state.apply_action(DexAction::RegisterCollection(
+            collection.cis_contract_address,
+            collection.info,
+        ));
+    }
+    Ok(state)
 }
 
 #[receive(
     contract = "RagnarDEX",
     name = "add",
     parameter = "AddParams",
+    crypto_primitives,
     mutable
 )]
 fn add<S: HasStateApi>(
     ctx: &impl HasReceiveContext,
     host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    crypto_primitives: &impl HasCryptoPrimitives,
 ) -> ContractResult<()> {
+    lock(host)?;
+    ensure_not_paused(host)?;
+
     let params: AddParams = ctx
         .parameter_cursor()
         .get()
         .map_err(|_e| DexError::ParseParams)?;
 
-    let sender_account_address: AccountAddress = match ctx.sender() {
-        Address::Account(account_address) => account_address,
-        Address::Contract(_) => bail!(DexError::CalledByAContract),
-    };
+    let sender = ctx.sender();
+    ensure_principal_allowed(host, &sender)?;
+    ensure_not_blocked(host, &[sender])?;
 
     let token_info = TokenInfo {
         address: params.cis_contract_address,
-        id: params.token_id,
+        id: params.token_id.clone(),
     };
 
+    ensure_collection_allowed(host, &params.cis_contract_address)?;
+    ensure_identity(
+        ctx,
+        host,
+        &token_info,
+        &sender,
+        &params.attestation,
+        crypto_primitives,
+    )?;
     ensure_supports_cis2(host, &params.cis_contract_address)?;
     ensure_is_operator(host, ctx, &params.cis_contract_address)?;
     ensure_balance(
         host,
         params.token_id,
         &params.cis_contract_address,
-        sender_account_address,
+        sender,
         params.quantity,
     )?;
 
-    
+    match params.mode {
+        PriceMode::Fixed => {}
+        PriceMode::Dutch { end_price, .. } => ensure!(
+            end_price.cmp(&params.price).is_le(),
+            DexError::InvalidPriceMode
+        ),
+        PriceMode::Oracle {
+            adjustment_basis_points,
+            ..
+        } => ensure!(
+            adjustment_basis_points >= -10_000,
+            DexError::InvalidPriceMode
+        ),
+    }
+
+    let now = ctx.metadata().slot_time();
+    if let Some(expires_at) = params.expires_at {
+        ensure!(expires_at > now, DexError::InvalidExpiry);
+    }
+
     host.state_mut().list_token(
         &token_info,
-        &sender_account_address,
-        params.price,
-        params.quantity,
+        &sender,
+        TokenPriceState {
+            price: params.price,
+            currency: params.currency,
+            mode: params.mode,
+            quantity: params.quantity,
+            listed_at: now,
+            expires_at: params.expires_at,
+            holder: sender,
+        },
     );
 
+    unlock(host);
     Ok(())
 }
 
@@ -107,6 +243,8 @@ fn add<S: HasStateApi>(
     contract = "RagnarDEX",
     name = "transfer",
     parameter = "TransferParams",
+    enable_logger,
+    crypto_primitives,
     mutable,
     payable
 )]
@@ -114,455 +252,5002 @@ fn transfer<S: HasStateApi>(
     ctx: &impl HasReceiveContext,
     host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
     amount: Amount,
+    logger: &mut impl HasLogger,
+    crypto_primitives: &impl HasCryptoPrimitives,
 ) -> ContractResult<()> {
+    lock(host)?;
+    ensure_not_paused(host)?;
+
     let params: TransferParams = ctx
         .parameter_cursor()
         .get()
         .map_err(|_e| DexError::ParseParams)?;
 
     let token_info = TokenInfo {
-        id: params.token_id,
+        id: params.token_id.clone(),
         address: params.cis_contract_address,
     };
 
-    let mut listed_token = host
+    ensure_collection_allowed(host, &params.cis_contract_address)?;
+    ensure_valid_referrer(ctx, &params)?;
+    ensure_principal_allowed(host, &params.to.address())?;
+    ensure_not_blocked(host, &[ctx.sender(), params.to.address(), params.owner])?;
+    ensure_identity(
+        ctx,
+        host,
+        &token_info,
+        &params.to.address(),
+        &params.attestation,
+        crypto_primitives,
+    )?;
+
+    let now = ctx.metadata().slot_time();
+    ensure_market_open(host, &token_info, now)?;
+    let listed_token = host
         .state_mut()
         .get_token(&token_info, &params.owner)
+        .filter(|listed_token| !listed_token.is_expired(now))
         .ok_or(DexError::TokenNotListed)?;
 
     let listed_quantity = listed_token.quantity;
-    let price_per_unit = listed_token.price;
+    let price_per_unit = unit_price_ccd(host, &listed_token, now)?;
 
     ensure!(
         listed_quantity.cmp(&params.quantity).is_ge(),
         DexError::InvalidTokenQuantity
     );
 
-    let price = price_per_unit * params.quantity.0;
+    let price = total_price(price_per_unit, params.quantity)?;
     ensure!(
         amount.cmp(&price).is_ge(),
         DexError::InvalidAmountPaid
     );
 
-    let cis2_client = Cis2Client::new(params.cis_contract_address);
-    let res: Cis2ClientResult<SupportResult> = cis2_client.supports_cis2(host);
-    let res = match res {
-        Ok(res) => res,
-        Err(_) => bail!(DexError::Cis2ClientError),
-    };
-    // Checks if the CIS2 contract supports the CIS2 interface.
-    let cis2_contract_address = match res {
-        SupportResult::NoSupport => bail!(DexError::CollectionNotCis2),
-        SupportResult::Support => params.cis_contract_address,
-        SupportResult::SupportBy(contracts) => match contracts.first() {
-            Some(c) => *c,
-            None => bail!(DexError::CollectionNotCis2),
-        },
+    ensure_listing_backed(
+        host,
+        ctx.self_address(),
+        &token_info,
+        listed_token.holder,
+        params.quantity,
+    )?;
+
+    record_trade(host, logger, &token_info, price_per_unit, now)?;
+    host.state_mut().decrease_listed_quantity(
+        &TokenOwnerInfo::from(token_info, &params.owner),
+        params.quantity,
+    );
+    distribute_amounts(
+        host,
+        price,
+        &params.cis_contract_address,
+        &params.owner,
+        &Address::Account(ctx.owner()),
+        params.referrer,
+        now,
+    );
+    // Buyers attach a margin for prices which move before the trade executes.
+    // Whatever they paid over the price is theirs to withdraw.
+    host.state_mut().credit_proceeds(&ctx.sender(), amount - price);
+
+    cis2_transfer(
+        host,
+        params.cis_contract_address,
+        params.token_id,
+        params.quantity,
+        listed_token.holder,
+        params.to, // User that bought the cis2 token
+    )?;
+
+    unlock(host);
+    Ok(())
+}
+
+/// Buys tokens through an order signed off-chain by their seller, who pays
+/// nothing until the order is filled. An order can be filled in parts, up to
+/// its quantity, until it expires or its maker cancels it.
+#[receive(
+    contract = "RagnarDEX",
+    name = "fillOrder",
+    parameter = "FillOrderParams",
+    enable_logger,
+    crypto_primitives,
+    mutable,
+    payable
+)]
+fn fill_order<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    amount: Amount,
+    logger: &mut impl HasLogger,
+    crypto_primitives: &impl HasCryptoPrimitives,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_not_paused(host)?;
+
+    let params: FillOrderParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+    let order = &params.order;
+    let maker = Address::Account(order.maker);
+
+    let now = ctx.metadata().slot_time();
+    ensure!(order.expiry > now, DexError::OrderExpired);
+    ensure!(
+        order.nonce >= host.state().min_nonce_of(&order.maker),
+        DexError::OrderCancelled
+    );
+
+    let token_info = TokenInfo {
+        id: order.token_id.clone(),
+        address: order.cis_contract_address,
     };
 
-    let cis2_client = Cis2Client::new(cis2_contract_address);
-    let res: Cis2ClientResult<bool> = cis2_client.transfer(
+    ensure_collection_allowed(host, &order.cis_contract_address)?;
+    ensure_principal_allowed(host, &params.to.address())?;
+    ensure_not_blocked(host, &[ctx.sender(), params.to.address(), maker])?;
+    // The maker sells like the seller of a listing, so it is held to the same
+    // identity requirements as `add`.
+    ensure_identity(
+        ctx,
         host,
-        Transfer {
-            amount: params.quantity,
-            from: Address::Contract(ctx.self_address()),
-            to: Receiver::Account(params.to),   // User that bought the cis2 token
-            token_id: params.token_id,
-            data: AdditionalData::empty(),
-        },
+        &token_info,
+        &maker,
+        &params.maker_attestation,
+        crypto_primitives,
+    )?;
+    ensure_identity(
+        ctx,
+        host,
+        &token_info,
+        &params.to.address(),
+        &params.attestation,
+        crypto_primitives,
+    )?;
+    ensure_market_open(host, &token_info, now)?;
+
+    let message = order_message(ctx, order);
+    let signed_hash = signed_message_hash(crypto_primitives, &order.maker, &message);
+    let signed = host.check_account_signature(order.maker, &params.signatures, &signed_hash.0);
+    ensure!(matches!(signed, Ok(true)), DexError::InvalidSignature);
+    let hash = crypto_primitives.hash_sha2_256(&message);
+
+    let filled = host
+        .state()
+        .filled_of(&hash)
+        .unwrap_or(TokenAmountU256(0u64.into()));
+    let filled = filled
+        .0
+        .checked_add(params.quantity.0)
+        .map(TokenAmountU256)
+        .ok_or(DexError::InvalidTokenQuantity)?;
+    ensure!(
+        !params.quantity.0.is_zero() && filled <= order.quantity,
+        DexError::InvalidTokenQuantity
     );
 
-    match res {
-        Ok(res) => res,
-        Err(_) => bail!(DexError::Cis2ClientError),
+    let price = total_price(order.price, params.quantity)?;
+    ensure!(
+        amount.cmp(&price).is_ge(),
+        DexError::InvalidAmountPaid
+    );
+
+    ensure_listing_backed(
+        host,
+        ctx.self_address(),
+        &token_info,
+        maker,
+        params.quantity,
+    )?;
+
+    record_trade(host, logger, &token_info, order.price, now)?;
+    let _ = host.state_mut().order_fills.insert(hash, filled);
+    distribute_amounts(
+        host,
+        price,
+        &order.cis_contract_address,
+        &maker,
+        &Address::Account(ctx.owner()),
+        None,
+        now,
+    );
+    host.state_mut().credit_proceeds(&ctx.sender(), amount - price);
+
+    cis2_transfer(
+        host,
+        order.cis_contract_address,
+        order.token_id.clone(),
+        params.quantity,
+        maker,
+        params.to,
+    )?;
+
+    unlock(host);
+    Ok(())
+}
+
+/// Cancels every signed order of the sender with a nonce below the given one.
+/// The minimum nonce can only be raised.
+#[receive(
+    contract = "RagnarDEX",
+    name = "cancelOrders",
+    parameter = "u64",
+    mutable
+)]
+fn cancel_orders<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let maker = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(DexError::CalledByAContract),
     };
+    let min_nonce: u64 = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+    ensure!(
+        min_nonce > host.state().min_nonce_of(&maker),
+        DexError::InvalidNonce
+    );
 
-    listed_token.price = listed_token.price + Amount::from_micro_ccd(1);
+    let _ = host.state_mut().min_nonces.insert(canonical(&maker), min_nonce);
 
-   
+    unlock(host);
     Ok(())
 }
 
+/// Returns the hash of an order, under which its fills are recorded.
+#[receive(
+    contract = "RagnarDEX",
+    name = "orderHash",
+    parameter = "Order",
+    return_value = "HashSha2256",
+    crypto_primitives
+)]
+fn view_order_hash<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    _host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    crypto_primitives: &impl HasCryptoPrimitives,
+) -> ContractResult<HashSha2256> {
+    let order: Order = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    Ok(order_hash(ctx, crypto_primitives, &order))
+}
+
+/// Returns the quantity bought so far through the order with the given hash.
+#[receive(
+    contract = "RagnarDEX",
+    name = "viewOrderFill",
+    parameter = "HashSha2256",
+    return_value = "ContractTokenAmount"
+)]
+fn view_order_fill<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<ContractTokenAmount> {
+    let hash: HashSha2256 = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    Ok(host
+        .state()
+        .filled_of(&hash)
+        .unwrap_or(TokenAmountU256(0u64.into())))
+}
+
+/// Returns the lowest nonce of the orders of an account which are not
+/// cancelled.
+#[receive(
+    contract = "RagnarDEX",
+    name = "minNonce",
+    parameter = "AccountAddress",
+    return_value = "u64"
+)]
+fn min_nonce<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    let maker: AccountAddress = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    Ok(host.state().min_nonce_of(&maker))
+}
+
+/// Receive hook for CIS2 tokens sent to the Dex.
+///
+/// This does not take the reentrancy lock, as CIS2 contracts call it while
+/// the Dex is taking custody of tokens in the middle of a trade.
+/// CCD cannot be sent here; the buyback reserve is funded through
+/// `depositReserve`.
 #[receive(
     contract = "RagnarDEX",
     name = "receive_ccd",
     parameter = "TransferParams",
-    payable,
     mutable
 )]
-fn receive_ccd<S: HasStateApi>( _ctx: &impl HasReceiveContext,
+fn receive_ccd<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
     _host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
-    _amount: Amount) -> ContractResult<()>{
+) -> ContractResult<()> {
     Ok(())
 }
 /// Allows for transferring selling the Cis2 token specified from the user of the Dex for CCD 
 ///
 /// This function is the function where one
-/// account can transfer an Asset by paying a price. The transfer will fail of
-/// the Amount paid is < token_quantity * token_price
+/// account can transfer an Asset by paying a price. The price is paid from
+/// the buyback reserve, and the transfer will fail if the reserve or the
+/// daily buyback limits do not cover token_quantity * token_price
 #[receive(
     contract = "RagnarDEX",
     name = "transfer_cis2",
     parameter = "TransferParams",
+    enable_logger,
+    crypto_primitives,
     mutable,
 )]
 fn transfer_cis2<S: HasStateApi>(
     ctx: &impl HasReceiveContext,
     host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+    crypto_primitives: &impl HasCryptoPrimitives,
 ) -> ContractResult<()> {
+    lock(host)?;
+    ensure_not_paused(host)?;
+
     let params: TransferParams = ctx
         .parameter_cursor()
         .get()
         .map_err(|_e| DexError::ParseParams)?;
 
     let token_info = TokenInfo {
-        id: params.token_id,
+        id: params.token_id.clone(),
         address: params.cis_contract_address,
     };
 
-    let mut listed_token = host
+    ensure_collection_allowed(host, &params.cis_contract_address)?;
+    ensure_valid_referrer(ctx, &params)?;
+    ensure_not_blocked(host, &[ctx.sender(), params.owner])?;
+    ensure_identity(
+        ctx,
+        host,
+        &token_info,
+        &params.owner,
+        &params.attestation,
+        crypto_primitives,
+    )?;
+
+    let now = ctx.metadata().slot_time();
+    ensure_market_open(host, &token_info, now)?;
+    let listed_token = host
         .state_mut()
         .get_token(&token_info, &params.owner)
+        .filter(|listed_token| !listed_token.is_expired(now))
         .ok_or(DexError::TokenNotListed)?;
 
     let listed_quantity = listed_token.quantity;
-    let price_per_unit = listed_token.price;
+    let price_per_unit = unit_price_ccd(host, &listed_token, now)?;
 
     ensure!(
         listed_quantity.cmp(&params.quantity).is_ge(),
         DexError::InvalidTokenQuantity
     );
 
-    let price = price_per_unit * params.quantity.0;
+    let price = total_price(price_per_unit, params.quantity)?;
+    let capacity = host
+        .state()
+        .buyback_capacity(&token_info, &params.owner, now);
     ensure!(
-        host.self_balance().cmp(&price).is_ge(),
+        capacity.reserve.cmp(&price).is_ge(),
         DexError::InsufficientFunds
     );
+    ensure!(
+        capacity.available.cmp(&price).is_ge(),
+        DexError::BuybackLimitExceeded
+    );
 
-    let cis2_client = Cis2Client::new(params.cis_contract_address);
-    let res: Cis2ClientResult<SupportResult> = cis2_client.supports_cis2(host);
-    let res = match res {
-        Ok(res) => res,
-        Err(_) => bail!(DexError::Cis2ClientError),
-    };
-    // Checks if the CIS2 contract supports the CIS2 interface.
-    let cis2_contract_address = match res {
-        SupportResult::NoSupport => bail!(DexError::CollectionNotCis2),
-        SupportResult::Support => params.cis_contract_address,
-        SupportResult::SupportBy(contracts) => match contracts.first() {
-            Some(c) => *c,
-            None => bail!(DexError::CollectionNotCis2),
-        },
-    };
+    ensure_listing_backed(
+        host,
+        ctx.self_address(),
+        &token_info,
+        listed_token.holder,
+        params.quantity,
+    )?;
 
-    let cis2_client = Cis2Client::new(cis2_contract_address);
-    let res: Cis2ClientResult<bool> = cis2_client.transfer(
+    host.state_mut()
+        .record_buyback(&token_info, &params.owner, price, now);
+    record_trade(host, logger, &token_info, price_per_unit, now)?;
+    host.state_mut().decrease_listed_quantity(
+        &TokenOwnerInfo::from(token_info, &params.owner),
+        params.quantity,
+    );
+    distribute_amounts(
         host,
-        Transfer {
-            amount: params.quantity,
-            from: Address::Account(params.owner),
-            to: Receiver::Contract(ctx.self_address(), OwnedEntrypointName::new("receive_ccd".to_string()).unwrap(),
-        ),   // User that receives the cis2 token
-            token_id: params.token_id,
-            data: AdditionalData::empty(),
-        },
+        price,
+        &params.cis_contract_address,
+        &params.owner,
+        &Address::Account(ctx.owner()),
+        params.referrer,
+        now,
     );
 
-    match res {
-        Ok(res) => res,
-        Err(_) => bail!(DexError::Cis2ClientError),
-    };
-
-    host.invoke_transfer(&params.owner, price)
-        .map_err(|_| DexError::InvokeTransferError)?;
-
-    // distribute_amounts(
-    //     host,
-    //     amount,
-    //     &params.owner,
-    //     &ctx.owner(),
-    // )?;
+    cis2_transfer(
+        host,
+        params.cis_contract_address,
+        params.token_id,
+        params.quantity,
+        listed_token.holder,
+        self_receiver(ctx), // The Dex receives the cis2 token
+    )?;
 
-    listed_token.price = listed_token.price - Amount::from_micro_ccd(1);
+    unlock(host);
     Ok(())
 }
 
 /// Returns a list of Added Cis2 Tokens and the token price
 #[receive(contract = "RagnarDEX", name = "list", return_value = "TokenList")]
 fn list<S: HasStateApi>(
-    _ctx: &impl HasReceiveContext,
+    ctx: &impl HasReceiveContext,
     host: &impl HasHost<ContractState<S>, StateApiType = S>,
 ) -> ContractResult<TokenList> {
     let tokens: Vec<TokenListItem<ContractTokenId, ContractTokenAmount>> = host
         .state()
-        .list()
+        .list(ctx.metadata().slot_time())
         .iter()
-        .filter(|t| t.quantity.cmp(&ContractTokenAmount::from(0)).is_gt())
+        .filter(|t| !t.quantity.0.is_zero())
         .cloned()
         .collect::<Vec<TokenListItem<ContractTokenId, ContractTokenAmount>>>();
 
     Ok(TokenList(tokens))
 }
 
-/// Calls the [supports](https://proposals.concordium.software/CIS/cis-0.html#supports) function of CIS2 contract.
-/// Returns error If the contract does not support the standard.
-fn ensure_supports_cis2<S: HasStateApi, T: IsTokenId + Copy, A: IsTokenAmount + Copy>(
-    host: &mut impl HasHost<State<S, T, A>, StateApiType = S>,
-    cis_contract_address: &ContractAddress,
-) -> ContractResult<()> {
-    let cis2_client = Cis2Client::new(*cis_contract_address);
-    let res: Cis2ClientResult<SupportResult> = cis2_client.supports_cis2(host);
+/// Returns the current price of a listed token in its own currency and in
+/// CCD, and the CCD to be paid by `transfer` for the given quantity.
+#[receive(
+    contract = "RagnarDEX",
+    name = "quote",
+    parameter = "QuoteParams",
+    return_value = "Quote"
+)]
+fn quote<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<Quote> {
+    let params: QuoteParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
 
-    let res = match res {
-        Ok(res) => res,
-        Err(_) => bail!(DexError::Cis2ClientError),
+    let token_info = TokenInfo {
+        id: params.token_id,
+        address: params.cis_contract_address,
     };
 
-    match res {
-        SupportResult::NoSupport => bail!(DexError::CollectionNotCis2),
-        SupportResult::SupportBy(_) => Ok(()),
-        SupportResult::Support => Ok(()),
-    }
+    let now = ctx.metadata().slot_time();
+    let listed_token = host
+        .state()
+        .get_token(&token_info, &params.owner)
+        .filter(|listed_token| !listed_token.is_expired(now))
+        .ok_or(DexError::TokenNotListed)?;
+
+    let unit_price_ccd = unit_price_ccd(host, &listed_token, now)?;
+
+    Ok(Quote {
+        unit_price: listing_unit_price(host, &listed_token, now)?,
+        currency: listed_token.currency,
+        unit_price_ccd,
+        total_ccd: total_price(unit_price_ccd, params.quantity)?,
+    })
 }
 
-/// Calls the [operatorOf](https://proposals.concordium.software/CIS/cis-2.html#operatorof) function of CIS contract.
-/// Returns error if Current Contract Address is not an Operator of Transaction
-/// Sender.
-fn ensure_is_operator<S: HasStateApi, T: IsTokenId + Copy, A: IsTokenAmount + Copy>(
-    host: &mut impl HasHost<State<S, T, A>, StateApiType = S>,
-    ctx: &impl HasReceiveContext<()>,
-    cis_contract_address: &ContractAddress,
-) -> ContractResult<()> {
-    let cis2_client = Cis2Client::new(*cis_contract_address);
-    let res: Cis2ClientResult<bool> =
-        cis2_client.operator_of(host, ctx.sender(), Address::Contract(ctx.self_address()));
-    let res = match res {
-        Ok(res) => res,
-        Err(_) => bail!(DexError::Cis2ClientError),
+/// Removes listings which have expired. Callable by anyone.
+///
+/// Scans at most the given number of listings with an expiry per call, so
+/// that the cost of a call stays bounded, and returns where the next call
+/// should continue.
+#[receive(
+    contract = "RagnarDEX",
+    name = "pruneExpired",
+    parameter = "PruneParams",
+    return_value = "PruneResult",
+    mutable
+)]
+fn prune_expired<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<PruneResult> {
+    lock(host)?;
+
+    let params: PruneParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let result = host.state_mut().prune_expired(
+        ctx.metadata().slot_time(),
+        params.start,
+        params.max_scanned,
+    );
+
+    unlock(host);
+    Ok(result)
+}
+
+/// Removes a listing whose seller no longer holds the listed quantity or no
+/// longer has the Dex as an operator. Callable by anyone.
+///
+/// A failed purchase rolls back all of its state changes, so a purchase of a
+/// stale listing cannot delist it. Instead the buyer gets a
+/// `SellerBalanceGone` or `OperatorRevoked` error, and anyone can then call
+/// this function to remove the listing. Returns whether the listing was
+/// removed.
+#[receive(
+    contract = "RagnarDEX",
+    name = "delistStale",
+    parameter = "ListingParams",
+    return_value = "bool",
+    enable_logger,
+    mutable
+)]
+fn delist_stale<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<bool> {
+    lock(host)?;
+
+    let params: ListingParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let token_info = TokenInfo {
+        id: params.token_id.clone(),
+        address: params.cis_contract_address,
     };
-    ensure!(res, DexError::NotOperator);
+
+    let listed_token = host
+        .state()
+        .get_token(&token_info, &params.owner)
+        .ok_or(DexError::TokenNotListed)?;
+
+    let reason = match ensure_listing_backed(
+        host,
+        ctx.self_address(),
+        &token_info,
+        listed_token.holder,
+        listed_token.quantity,
+    ) {
+        Ok(()) => {
+            unlock(host);
+            return Ok(false);
+        }
+        Err(DexError::SellerBalanceGone) => DelistReason::SellerBalanceGone,
+        Err(DexError::OperatorRevoked) => DelistReason::OperatorRevoked,
+        Err(e) => bail!(e),
+    };
+
+    host.state_mut()
+        .remove_listing(&TokenOwnerInfo::from(token_info, &params.owner));
+
+    logger.log(&DexEvent::ListingRemoved(ListingRemovedEvent {
+        token_id: params.token_id,
+        contract: params.cis_contract_address,
+        owner: listed_token.holder,
+        reason,
+    }))?;
+
+    unlock(host);
+    Ok(true)
+}
+
+/// Pays out the proceeds owed to the sender. Contracts collect their proceeds
+/// with `withdrawToContract`. Blocked accounts cannot withdraw.
+#[receive(contract = "RagnarDEX", name = "withdraw", mutable)]
+fn withdraw<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let sender_account_address: AccountAddress = match ctx.sender() {
+        Address::Account(account_address) => account_address,
+        Address::Contract(_) => bail!(DexError::CalledByAContract),
+    };
+    ensure_not_blocked(host, &[ctx.sender()])?;
+
+    let amount = host
+        .state_mut()
+        .take_proceeds(&Address::Account(sender_account_address));
+    ensure!(
+        amount.cmp(&Amount::zero()).is_gt(),
+        DexError::NoProceeds
+    );
+
+    host.invoke_transfer(&sender_account_address, amount)
+        .map_err(|_| DexError::InvokeTransferError)?;
+
+    unlock(host);
     Ok(())
 }
 
-/// Calls the [balanceOf](https://proposals.concordium.software/CIS/cis-2.html#balanceof) function of the CIS2 contract.
-/// Returns error if the returned balance < input balance (balance param).
-fn ensure_balance<S: HasStateApi, T: IsTokenId + Copy, A: IsTokenAmount + Ord + Copy>(
-    host: &mut impl HasHost<State<S, T, A>, StateApiType = S>,
-    token_id: T,
-    cis_contract_address: &ContractAddress,
-    owner: AccountAddress,
-    minimum_balance: A,
+/// Pays out the proceeds owed to the sending contract, by calling the given
+/// entrypoint of the sender with the amount.
+#[receive(
+    contract = "RagnarDEX",
+    name = "withdrawToContract",
+    parameter = "OwnedEntrypointName",
+    mutable
+)]
+fn withdraw_to_contract<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
 ) -> ContractResult<()> {
-    let cis2_client = Cis2Client::new(*cis_contract_address);
+    lock(host)?;
+
+    let sender_contract_address: ContractAddress = match ctx.sender() {
+        Address::Contract(contract_address) => contract_address,
+        Address::Account(_) => bail!(DexError::Unauthorized),
+    };
+    ensure_not_blocked(host, &[ctx.sender()])?;
+
+    let entrypoint: OwnedEntrypointName = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let amount = host
+        .state_mut()
+        .take_proceeds(&Address::Contract(sender_contract_address));
+    ensure!(
+        amount.cmp(&Amount::zero()).is_gt(),
+        DexError::NoProceeds
+    );
+
+    // The lock is held during the call, so the receiving contract cannot call
+    // back into the Dex.
+    host.invoke_contract(
+        &sender_contract_address,
+        &(),
+        entrypoint.as_entrypoint_name(),
+        amount,
+    )
+    .map_err(|_| DexError::InvokeTransferError)?;
+
+    unlock(host);
+    Ok(())
+}
+
+/// Returns the proceeds owed to an account or contract.
+#[receive(
+    contract = "RagnarDEX",
+    name = "balanceOfProceeds",
+    parameter = "Address",
+    return_value = "Amount"
+)]
+fn balance_of_proceeds<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<Amount> {
+    let address: Address = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    Ok(host.state().proceeds_of(&address))
+}
+
+/// Adds the CCD sent with the call to the buyback reserve. Owner only.
+#[receive(contract = "RagnarDEX", name = "depositReserve", mutable, payable)]
+fn deposit_reserve<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    amount: Amount,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_owner(ctx)?;
+
+    host.state_mut().reserve += amount;
+
+    unlock(host);
+    Ok(())
+}
+
+/// Takes CCD out of the buyback reserve and sends it to the owner. Owner only,
+/// until multisig admins are set.
+#[receive(
+    contract = "RagnarDEX",
+    name = "withdrawReserve",
+    parameter = "Amount",
+    mutable
+)]
+fn withdraw_reserve<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_sole_owner(ctx, host)?;
+
+    let amount: Amount = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(ctx, host, AdminAction::WithdrawReserve(amount))
+}
+
+/// Sets the daily caps on buyback spend. Owner only, until multisig admins are
+/// set.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setBuybackLimits",
+    parameter = "BuybackLimits",
+    mutable
+)]
+fn set_buyback_limits<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_sole_owner(ctx, host)?;
+
+    let limits: BuybackLimits = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(ctx, host, AdminAction::SetBuybackLimits(limits))
+}
+
+/// Returns the buyback capacity left today for a token sold by an address.
+#[receive(
+    contract = "RagnarDEX",
+    name = "buybackCapacity",
+    parameter = "ListingParams",
+    return_value = "BuybackCapacity"
+)]
+fn buyback_capacity<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<BuybackCapacity> {
+    let params: ListingParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let token_info = TokenInfo {
+        id: params.token_id,
+        address: params.cis_contract_address,
+    };
+
+    Ok(host
+        .state()
+        .buyback_capacity(&token_info, &params.owner, ctx.metadata().slot_time()))
+}
+
+/// Approves a CIS2 collection for trading, or updates its metadata. Owner
+/// only, until multisig admins or governance take over.
+#[receive(
+    contract = "RagnarDEX",
+    name = "registerCollection",
+    parameter = "RegisterCollectionParams",
+    mutable
+)]
+fn register_collection<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_admin(ctx, host)?;
+
+    let params: RegisterCollectionParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(
+        ctx,
+        host,
+        AdminAction::Dex(DexAction::RegisterCollection(
+            params.cis_contract_address,
+            params.info,
+        )),
+    )
+}
+
+/// Removes a CIS2 collection from the registry. Owner only, until multisig
+/// admins or governance take over.
+///
+/// Existing listings of the collection stay in state, but cannot be bought
+/// while the collection is not approved.
+#[receive(
+    contract = "RagnarDEX",
+    name = "removeCollection",
+    parameter = "ContractAddress",
+    mutable
+)]
+fn remove_collection<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_admin(ctx, host)?;
+
+    let cis_contract_address: ContractAddress = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(
+        ctx,
+        host,
+        AdminAction::Dex(DexAction::RemoveCollection(cis_contract_address)),
+    )
+}
+
+/// Turns permissionless mode on or off. In permissionless mode any CIS2
+/// collection can be traded. Owner only, until multisig admins or governance
+/// take over.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setPermissionless",
+    parameter = "bool",
+    mutable
+)]
+fn set_permissionless<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_admin(ctx, host)?;
+
+    let permissionless: bool = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(
+        ctx,
+        host,
+        AdminAction::Dex(DexAction::SetPermissionless(permissionless)),
+    )
+}
+
+/// Allows or disallows smart contracts as listing owners and as receivers of
+/// bought tokens. Owner only, until multisig admins or governance take over.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setContractPrincipals",
+    parameter = "bool",
+    mutable
+)]
+fn set_contract_principals<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_admin(ctx, host)?;
+
+    let allowed: bool = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(
+        ctx,
+        host,
+        AdminAction::Dex(DexAction::SetContractPrincipals(allowed)),
+    )
+}
+
+/// Sets or removes the royalty paid on sales of a collection's tokens, up to
+/// `MAX_ROYALTY_BASIS_POINTS`. A raise applies once the admin delay has passed,
+/// so that sellers can delist their tokens first.
+///
+/// Only the owner of the CIS2 contract instance can call this, as recorded in
+/// the collection registry by `registerCollection`.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setRoyalty",
+    parameter = "SetRoyaltyParams",
+    mutable
+)]
+fn set_royalty<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let params: SetRoyaltyParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let collection_owner = host
+        .state()
+        .collections
+        .get(&params.cis_contract_address)
+        .and_then(|info| info.owner)
+        .ok_or(DexError::Unauthorized)?;
+    ensure!(sender_is(ctx, &collection_owner), DexError::Unauthorized);
+
+    if let Some(royalty) = params.royalty {
+        ensure!(
+            royalty.basis_points <= MAX_ROYALTY_BASIS_POINTS,
+            DexError::InvalidRoyalty
+        );
+    }
+    let now = ctx.metadata().slot_time();
+    let effective_at = now
+        .checked_add(host.state().admin_delay)
+        .ok_or(DexError::InvalidRoyalty)?;
+    host.state_mut().set_royalty(params.cis_contract_address, params.royalty, now, effective_at);
+
+    unlock(host);
+    Ok(())
+}
+
+/// Sets or removes further identity claims needed to trade a collection's
+/// tokens. Only callable by the owner of the collection recorded at
+/// registration. Traders have to meet both this and the marketplace policy.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setCollectionPolicy",
+    parameter = "SetCollectionPolicyParams",
+    mutable
+)]
+fn set_collection_policy<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let params: SetCollectionPolicyParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    ensure_collection_owner(ctx, host, &params.cis_contract_address)?;
+
+    match params.policy {
+        Some(policy) => {
+            let _ = host
+                .state_mut()
+                .collection_policies
+                .insert(params.cis_contract_address, policy);
+        }
+        None => {
+            host.state_mut()
+                .collection_policies
+                .remove(&params.cis_contract_address);
+        }
+    }
+
+    unlock(host);
+    Ok(())
+}
+
+/// Sets or removes further identity claims needed to trade a single token of
+/// a collection. Only callable by the owner of the collection recorded at
+/// registration. Traders have to meet this on top of the collection and
+/// marketplace policies.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setTokenPolicy",
+    parameter = "SetTokenPolicyParams",
+    mutable
+)]
+fn set_token_policy<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let params: SetTokenPolicyParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    ensure_collection_owner(ctx, host, &params.cis_contract_address)?;
+
+    let market = TokenInfo {
+        id: params.token_id,
+        address: params.cis_contract_address,
+    };
+    match params.policy {
+        Some(policy) => {
+            let _ = host.state_mut().token_policies.insert(market, policy);
+        }
+        None => {
+            host.state_mut().token_policies.remove(&market);
+        }
+    }
+
+    unlock(host);
+    Ok(())
+}
+
+/// Sets or removes the identity claims every trader in the marketplace needs.
+/// Owner only, until multisig admins or governance take over.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setIdentityPolicy",
+    parameter = "Option<IdentityPolicy>",
+    mutable
+)]
+fn set_identity_policy<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_admin(ctx, host)?;
+
+    let policy: Option<IdentityPolicy> = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(
+        ctx,
+        host,
+        AdminAction::Dex(DexAction::SetIdentityPolicy(policy)),
+    )
+}
+
+/// Trusts or stops trusting the identity claims signed by a verifier. Owner
+/// only, until multisig admins or governance take over.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setVerifier",
+    parameter = "SetVerifierParams",
+    mutable
+)]
+fn set_verifier<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_admin(ctx, host)?;
+
+    let params: SetVerifierParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(
+        ctx,
+        host,
+        AdminAction::Dex(DexAction::SetVerifier(params.verifier, params.trusted)),
+    )
+}
+
+/// Returns the identity policies a trader of a token has to meet.
+#[receive(
+    contract = "RagnarDEX",
+    name = "viewIdentityPolicies",
+    parameter = "MarketParams",
+    return_value = "Vec<IdentityPolicy>"
+)]
+fn view_identity_policies<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<Vec<IdentityPolicy>> {
+    let params: MarketParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let market = TokenInfo {
+        id: params.token_id,
+        address: params.cis_contract_address,
+    };
+    Ok(host.state().identity_policies_of(&market))
+}
+
+/// Posts identity claims to the attestation registry, replacing any earlier
+/// claims about the same subject. Only callable by a trusted verifier.
+#[receive(
+    contract = "RagnarDEX",
+    name = "postAttestation",
+    parameter = "IdentityClaims",
+    mutable
+)]
+fn post_attestation<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let verifier = match ctx.sender() {
+        Address::Account(account) if host.state().is_verifier(&account) => account,
+        _ => bail!(DexError::UnknownVerifier),
+    };
+
+    let claims: IdentityClaims = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+    ensure!(
+        claims.expires_at > ctx.metadata().slot_time(),
+        DexError::InvalidAttestation
+    );
+
+    let _ = host.state_mut().attestations.insert(
+        canonical_address(&claims.subject),
+        AttestationRecord {
+            verifier,
+            claims,
+            revoked: false,
+        },
+    );
+
+    unlock(host);
+    Ok(())
+}
+
+/// Revokes the claims about an address in the attestation registry. Only
+/// callable by the verifier which posted them.
+#[receive(
+    contract = "RagnarDEX",
+    name = "revokeAttestation",
+    parameter = "Address",
+    mutable
+)]
+fn revoke_attestation<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let subject: Address = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    {
+        let mut record = host
+            .state_mut()
+            .attestations
+            .get_mut(&canonical_address(&subject))
+            .ok_or(DexError::AttestationRequired)?;
+        ensure!(sender_is(ctx, &record.verifier), DexError::Unauthorized);
+        record.revoked = true;
+    }
+
+    unlock(host);
+    Ok(())
+}
+
+/// Returns why an address cannot trade a token with the claims in the
+/// attestation registry, or `None` if it can.
+#[receive(
+    contract = "RagnarDEX",
+    name = "checkEligibility",
+    parameter = "EligibilityQuery",
+    return_value = "Option<IneligibleReason>"
+)]
+fn check_eligibility<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<Option<IneligibleReason>> {
+    let query: EligibilityQuery = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let market = TokenInfo {
+        id: query.token_id,
+        address: query.cis_contract_address,
+    };
+    Ok(host
+        .state()
+        .check_eligibility(&market, &query.address, ctx.metadata().slot_time())
+        .err())
+}
+
+/// Returns the royalty paid on sales of a collection's tokens, if any.
+#[receive(
+    contract = "RagnarDEX",
+    name = "viewRoyalty",
+    parameter = "ContractAddress",
+    return_value = "Option<Royalty>"
+)]
+fn view_royalty<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<Option<Royalty>> {
+    let cis_contract_address: ContractAddress = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    Ok(host.state().royalty_of(&cis_contract_address, ctx.metadata().slot_time()))
+}
+
+/// Returns the raise of a collection's royalty which has not applied yet, if
+/// any.
+#[receive(
+    contract = "RagnarDEX",
+    name = "viewRoyaltyChange",
+    parameter = "ContractAddress",
+    return_value = "Option<RoyaltyChange>"
+)]
+fn view_royalty_change<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<Option<RoyaltyChange>> {
+    let cis_contract_address: ContractAddress = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    Ok(host.state().royalty_change_of(&cis_contract_address, ctx.metadata().slot_time()))
+}
+
+/// Sets the commission and the share of it paid to referrers. Owner only,
+/// until multisig admins or governance take over.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setFees",
+    parameter = "FeeConfig",
+    mutable
+)]
+fn set_fees<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_admin(ctx, host)?;
+
+    let fees: FeeConfig = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(ctx, host, AdminAction::Dex(DexAction::SetFees(fees)))
+}
+
+/// Returns the referral rewards earned by an account.
+#[receive(
+    contract = "RagnarDEX",
+    name = "referralStats",
+    parameter = "AccountAddress",
+    return_value = "ReferralStats"
+)]
+fn referral_stats<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<ReferralStats> {
+    let referrer: AccountAddress = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    Ok(host.state().referral_stats_of(&referrer))
+}
+
+/// Pauses or resumes trading. Owner only, until multisig admins are set. Once
+/// governance is configured, the owner can still pause but not resume.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setPaused",
+    parameter = "bool",
+    mutable
+)]
+fn set_paused<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_sole_owner(ctx, host)?;
+
+    let paused: bool = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(ctx, host, AdminAction::Dex(DexAction::SetPaused(paused)))
+}
+
+/// Hands control over the marketplace parameters to token-holder governance.
+/// Owner only, until multisig admins are set, and can only be called once.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setGovernance",
+    parameter = "ContractGovernanceConfig",
+    mutable
+)]
+fn set_governance<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_admin(ctx, host)?;
+
+    let governance: ContractGovernanceConfig = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(ctx, host, AdminAction::SetGovernance(governance))
+}
+
+/// Creates a proposal to change the marketplace parameters, and returns its
+/// id. The proposer must hold governance tokens.
+#[receive(
+    contract = "RagnarDEX",
+    name = "propose",
+    parameter = "DexAction",
+    return_value = "u64",
+    mutable
+)]
+fn propose<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    lock(host)?;
+
+    let action: DexAction = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let proposer = match ctx.sender() {
+        Address::Account(account_address) => account_address,
+        Address::Contract(_) => bail!(DexError::CalledByAContract),
+    };
+
+    let governance = host
+        .state()
+        .governance
+        .clone()
+        .ok_or(DexError::GovernanceNotConfigured)?;
+    ensure_valid_action(&action)?;
+
+    let now = ctx.metadata().slot_time();
+    ensure!(
+        !voting_weight(host, &governance, proposer, now)?.0.is_zero(),
+        DexError::NoVotingPower
+    );
+
+    let voting_ends = now
+        .checked_add(governance.voting_period)
+        .ok_or(DexError::InvalidGovernance)?;
+    let executable_at = voting_ends
+        .checked_add(governance.timelock)
+        .ok_or(DexError::InvalidGovernance)?;
+
+    let proposal_id = host.state_mut().create_proposal(ContractProposal {
+        proposer,
+        action,
+        snapshot: now,
+        voting_ends,
+        executable_at,
+        votes_for: TokenAmountU256(0u64.into()),
+        votes_against: TokenAmountU256(0u64.into()),
+        executed: false,
+    });
+
+    unlock(host);
+    Ok(proposal_id)
+}
+
+/// Votes on an open proposal, with the governance token balance held at the
+/// proposal's snapshot. Each account can vote once per proposal.
+#[receive(
+    contract = "RagnarDEX",
+    name = "vote",
+    parameter = "VoteParams",
+    mutable
+)]
+fn vote<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let params: VoteParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let voter = match ctx.sender() {
+        Address::Account(account_address) => canonical(&account_address),
+        Address::Contract(_) => bail!(DexError::CalledByAContract),
+    };
+
+    let governance = host
+        .state()
+        .governance
+        .clone()
+        .ok_or(DexError::GovernanceNotConfigured)?;
+    let proposal = host
+        .state()
+        .get_proposal(params.proposal_id)
+        .ok_or(DexError::ProposalNotFound)?;
+
+    ensure!(
+        ctx.metadata().slot_time() < proposal.voting_ends,
+        DexError::VotingClosed
+    );
+    ensure!(
+        !host.state().votes.contains(&(params.proposal_id, voter)),
+        DexError::AlreadyVoted
+    );
+
+    let weight = voting_weight(host, &governance, voter, proposal.snapshot)?;
+    ensure!(!weight.0.is_zero(), DexError::NoVotingPower);
+
+    let state = host.state_mut();
+    state.votes.insert((params.proposal_id, voter));
+    state
+        .proposals
+        .entry(params.proposal_id)
+        .and_modify(|proposal| {
+            if params.support {
+                proposal.votes_for += weight;
+            } else {
+                proposal.votes_against += weight;
+            }
+        });
+
+    unlock(host);
+    Ok(())
+}
+
+/// Applies a proposal which reached the quorum and threshold, once its
+/// timelock has elapsed. Anyone can call this.
+#[receive(
+    contract = "RagnarDEX",
+    name = "executeProposal",
+    parameter = "u64",
+    mutable
+)]
+fn execute_proposal<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let proposal_id: u64 = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let governance = host
+        .state()
+        .governance
+        .clone()
+        .ok_or(DexError::GovernanceNotConfigured)?;
+    let proposal = host
+        .state()
+        .get_proposal(proposal_id)
+        .ok_or(DexError::ProposalNotFound)?;
+
+    ensure!(!proposal.executed, DexError::ProposalExecuted);
+    ensure!(
+        ctx.metadata().slot_time() >= proposal.executable_at,
+        DexError::TimelockNotElapsed
+    );
+    ensure!(has_passed(&proposal, &governance), DexError::ProposalNotPassed);
+
+    let state = host.state_mut();
+    state
+        .proposals
+        .entry(proposal_id)
+        .and_modify(|proposal| proposal.executed = true);
+    state.apply_action(proposal.action);
+
+    unlock(host);
+    Ok(())
+}
+
+/// Returns a governance proposal and its votes.
+#[receive(
+    contract = "RagnarDEX",
+    name = "viewProposal",
+    parameter = "u64",
+    return_value = "ContractProposal"
+)]
+fn view_proposal<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<ContractProposal> {
+    let proposal_id: u64 = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    host.state()
+        .get_proposal(proposal_id)
+        .ok_or(DexError::ProposalNotFound)
+}
+
+/// Hands the privileged entrypoints over to an M-of-N set of signers. Owner
+/// only, until multisig admins are set. Afterwards the signers change with a
+/// `SetAdmins` admin action.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setAdmins",
+    parameter = "MultisigConfig",
+    mutable
+)]
+fn set_admins<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_sole_owner(ctx, host)?;
+
+    let admins: MultisigConfig = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(ctx, host, AdminAction::SetAdmins(admins))
+}
+
+/// Proposes an admin action, approved by the proposing signer, and returns
+/// its id. Signers only.
+#[receive(
+    contract = "RagnarDEX",
+    name = "proposeAdminAction",
+    parameter = "ContractAdminAction",
+    return_value = "u64",
+    mutable
+)]
+fn propose_admin_action<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    lock(host)?;
+
+    let action: ContractAdminAction = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let signer = ensure_signer(ctx, host)?;
+    ensure_valid_admin_action(host, &action)?;
+
+    let proposal_id = host.state_mut().create_admin_proposal(ContractAdminProposal {
+        action,
+        approvals: Vec::new(),
+    });
+    add_approval(ctx, host, proposal_id, signer)?;
+
+    Ok(proposal_id)
+}
+
+/// Approves an admin action. The action is queued once the threshold of
+/// signers approved it. Signers only.
+#[receive(
+    contract = "RagnarDEX",
+    name = "approveAdminAction",
+    parameter = "u64",
+    mutable
+)]
+fn approve_admin_action<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let proposal_id: u64 = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let signer = ensure_signer(ctx, host)?;
+    add_approval(ctx, host, proposal_id, signer)
+}
+
+/// Returns an admin action waiting for approvals.
+#[receive(
+    contract = "RagnarDEX",
+    name = "viewAdminAction",
+    parameter = "u64",
+    return_value = "ContractAdminProposal"
+)]
+fn view_admin_action<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<ContractAdminProposal> {
+    let proposal_id: u64 = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    host.state()
+        .get_admin_proposal(proposal_id)
+        .ok_or(DexError::AdminActionNotFound)
+}
+
+/// Sets or removes the circuit breaker of the markets. Owner only, until
+/// multisig admins or governance take over.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setCircuitBreaker",
+    parameter = "Option<CircuitBreakerConfig>",
+    mutable
+)]
+fn set_circuit_breaker<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_admin(ctx, host)?;
+
+    let config: Option<CircuitBreakerConfig> = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(
+        ctx,
+        host,
+        AdminAction::Dex(DexAction::SetCircuitBreaker(config)),
+    )
+}
+
+/// Returns the circuit breaker of a market, which tells whether trading in it
+/// is halted and until when.
+#[receive(
+    contract = "RagnarDEX",
+    name = "viewMarket",
+    parameter = "MarketParams",
+    return_value = "Option<MarketBreaker>"
+)]
+fn view_market<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<Option<MarketBreaker>> {
+    let params: MarketParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let market = TokenInfo {
+        id: params.token_id,
+        address: params.cis_contract_address,
+    };
+    Ok(host.state().breakers.get(&market).map(|breaker| *breaker))
+}
+
+/// Returns the time-weighted average CCD price per unit of a market over a
+/// window before now, from the prices of its trades and settled auctions.
+/// Fails with `PriceHistoryTooShort` if the market has no trade before the
+/// window, or if the trades kept in the history do not reach back that far.
+#[receive(
+    contract = "RagnarDEX",
+    name = "consult",
+    parameter = "ConsultParams",
+    return_value = "Amount"
+)]
+fn consult<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<Amount> {
+    let params: ConsultParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+    ensure!(
+        params.window.millis() > 0,
+        DexError::InvalidPriceWindow
+    );
+
+    let market = TokenInfo {
+        id: params.token_id,
+        address: params.cis_contract_address,
+    };
+    host.state()
+        .consult(&market, params.window, ctx.metadata().slot_time())
+        .ok_or(DexError::PriceHistoryTooShort)
+}
+
+/// Sets the account which manages the blocklist, or removes it. Owner only,
+/// until multisig admins are set.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setCompliance",
+    parameter = "Option<AccountAddress>",
+    mutable
+)]
+fn set_compliance<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_sole_owner(ctx, host)?;
+
+    let compliance: Option<AccountAddress> = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(ctx, host, AdminAction::SetCompliance(compliance))
+}
+
+/// Adds an address to the blocklist. Compliance only. Takes effect at once.
+#[receive(
+    contract = "RagnarDEX",
+    name = "block",
+    parameter = "Address",
+    enable_logger,
+    mutable
+)]
+fn block<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_compliance(ctx, host)?;
+
+    let address: Address = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    if host.state_mut().blocked.insert(canonical_address(&address)) {
+        logger.log(&DexEvent::Blocked(BlocklistEvent { address }))?;
+    }
+
+    unlock(host);
+    Ok(())
+}
+
+/// Removes an address from the blocklist. Compliance only.
+#[receive(
+    contract = "RagnarDEX",
+    name = "unblock",
+    parameter = "Address",
+    enable_logger,
+    mutable
+)]
+fn unblock<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_compliance(ctx, host)?;
+
+    let address: Address = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    if host.state_mut().blocked.remove(&canonical_address(&address)) {
+        logger.log(&DexEvent::Unblocked(BlocklistEvent { address }))?;
+    }
+
+    unlock(host);
+    Ok(())
+}
+
+/// Returns whether any of the addresses is on the blocklist. Queried by
+/// `techFiestaToken` on every transfer and mint.
+#[receive(
+    contract = "RagnarDEX",
+    name = "isBlocked",
+    parameter = "Vec<Address>",
+    return_value = "bool"
+)]
+fn is_blocked<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<bool> {
+    let addresses: Vec<Address> = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    Ok(addresses
+        .iter()
+        .any(|address| host.state().is_blocked(address)))
+}
+
+/// Sets how long admin actions wait in the queue before they can run. Owner
+/// only, until multisig admins are set.
+#[receive(
+    contract = "RagnarDEX",
+    name = "setAdminDelay",
+    parameter = "Duration",
+    mutable
+)]
+fn set_admin_delay<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_sole_owner(ctx, host)?;
+
+    let delay: Duration = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    queue_admin_action(ctx, host, AdminAction::SetAdminDelay(delay))
+}
+
+/// Runs a queued admin action once its delay has passed. Anyone can call
+/// this.
+#[receive(
+    contract = "RagnarDEX",
+    name = "executeAdminAction",
+    parameter = "u64",
+    mutable
+)]
+fn execute_queued_admin_action<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let queue_id: u64 = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let queued = host
+        .state()
+        .get_queued_admin_action(queue_id)
+        .ok_or(DexError::AdminActionNotFound)?;
+    ensure!(
+        ctx.metadata().slot_time() >= queued.executable_at,
+        DexError::TimelockNotElapsed
+    );
+    ensure_valid_admin_action(host, &queued.action)?;
+    host.state_mut().admin_queue.remove(&queue_id);
+
+    // The action runs after the lock is released, so that the migration
+    // entrypoint of an upgraded module can be called.
+    unlock(host);
+    execute_admin_action(ctx, host, queued.action)
+}
+
+/// Cancels a queued admin action. Any single signer can cancel, or the owner
+/// while no multisig admins are set.
+#[receive(
+    contract = "RagnarDEX",
+    name = "cancelAdminAction",
+    parameter = "u64",
+    mutable
+)]
+fn cancel_admin_action<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    lock(host)?;
+    if host.state().admins.is_some() {
+        ensure_signer(ctx, host)?;
+    } else {
+        ensure_owner(ctx)?;
+    }
+
+    let queue_id: u64 = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    ensure!(
+        host.state().get_queued_admin_action(queue_id).is_some(),
+        DexError::AdminActionNotFound
+    );
+    host.state_mut().admin_queue.remove(&queue_id);
+
+    unlock(host);
+    Ok(())
+}
+
+/// Returns the queued admin actions, with the time from which they can run.
+#[receive(
+    contract = "RagnarDEX",
+    name = "viewAdminQueue",
+    return_value = "AdminQueue"
+)]
+fn view_admin_queue<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<AdminQueue> {
+    Ok(AdminQueue(host.state().queued_admin_actions()))
+}
+
+/// Returns the approved CIS2 collections and their metadata.
+#[receive(
+    contract = "RagnarDEX",
+    name = "viewCollections",
+    return_value = "CollectionRegistry"
+)]
+fn view_collections<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<CollectionRegistry> {
+    let state = host.state();
+    let collections = state
+        .collections
+        .iter()
+        .map(|(address, info)| (*address, info.clone()))
+        .collect();
+
+    Ok(CollectionRegistry {
+        permissionless: state.permissionless,
+        collections,
+    })
+}
+
+/// Starts an English auction for a token.
+///
+/// The token is moved into the custody of the Dex until the auction is
+/// settled. Contracts can auction tokens once contract principals are allowed.
+/// Returns the id of the new auction.
+#[receive(
+    contract = "RagnarDEX",
+    name = "createAuction",
+    parameter = "CreateAuctionParams",
+    return_value = "u64",
+    crypto_primitives,
+    mutable
+)]
+fn create_auction<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    crypto_primitives: &impl HasCryptoPrimitives,
+) -> ContractResult<u64> {
+    lock(host)?;
+    ensure_not_paused(host)?;
+
+    let params: CreateAuctionParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let seller = ctx.sender();
+    ensure_principal_allowed(host, &seller)?;
+
+    ensure!(
+        !params.quantity.0.is_zero(),
+        DexError::InvalidTokenQuantity
+    );
+    ensure!(
+        params.min_increment.cmp(&Amount::zero()).is_gt()
+            && params.start < params.end
+            && ctx.metadata().slot_time() < params.end
+            && canonical_address(&params.return_to.address()) == canonical_address(&seller),
+        DexError::InvalidAuctionParams
+    );
+
+    let market = TokenInfo {
+        id: params.token_id.clone(),
+        address: params.cis_contract_address,
+    };
+    ensure_collection_allowed(host, &params.cis_contract_address)?;
+    ensure_not_blocked(host, &[seller])?;
+    ensure_identity(
+        ctx,
+        host,
+        &market,
+        &seller,
+        &params.attestation,
+        crypto_primitives,
+    )?;
+    ensure_supports_cis2(host, &params.cis_contract_address)?;
+    ensure_is_operator(host, ctx, &params.cis_contract_address)?;
+    ensure_balance(
+        host,
+        params.token_id.clone(),
+        &params.cis_contract_address,
+        seller,
+        params.quantity,
+    )?;
+
+    let auction_id = host.state_mut().create_auction(AuctionState {
+        seller,
+        return_to: params.return_to,
+        contract: params.cis_contract_address,
+        token_id: params.token_id.clone(),
+        quantity: params.quantity,
+        reserve_price: params.reserve_price,
+        min_increment: params.min_increment,
+        start: params.start,
+        end: params.end,
+        highest_bid: None,
+    });
+
+    cis2_transfer(
+        host,
+        params.cis_contract_address,
+        params.token_id,
+        params.quantity,
+        seller,
+        self_receiver(ctx),
+    )?;
+
+    unlock(host);
+    Ok(auction_id)
+}
+
+/// Places a bid on an auction.
+///
+/// The bid must be at least the reserve price, or the current highest bid
+/// plus the minimum increment. The previous highest bidder is refunded, a
+/// contract through `withdrawToContract`. A bid within `AUCTION_EXTENSION` of
+/// the end pushes the end time back so that other bidders get the chance to
+/// respond.
+#[receive(
+    contract = "RagnarDEX",
+    name = "bid",
+    parameter = "BidParams",
+    crypto_primitives,
+    mutable,
+    payable
+)]
+fn bid<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    amount: Amount,
+    crypto_primitives: &impl HasCryptoPrimitives,
+) -> ContractResult<()> {
+    lock(host)?;
+    ensure_not_paused(host)?;
+
+    let params: BidParams = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+    let auction_id = params.auction_id;
+
+    let bidder = ctx.sender();
+    ensure_principal_allowed(host, &bidder)?;
+    ensure_principal_allowed(host, &params.to.address())?;
+    ensure_not_blocked(host, &[bidder, params.to.address()])?;
+    let market = {
+        let auction = host
+            .state()
+            .auctions
+            .get(&auction_id)
+            .ok_or(DexError::AuctionNotFound)?;
+        TokenInfo {
+            id: auction.token_id.clone(),
+            address: auction.contract,
+        }
+    };
+    ensure_identity(
+        ctx,
+        host,
+        &market,
+        &params.to.address(),
+        &params.attestation,
+        crypto_primitives,
+    )?;
+
+    let now = ctx.metadata().slot_time();
+    ensure_market_open(host, &market, now)?;
+    let previous_bid = {
+        let mut auction = host
+            .state_mut()
+            .auctions
+            .get_mut(&auction_id)
+            .ok_or(DexError::AuctionNotFound)?;
+
+        ensure!(
+            auction.start <= now && now < auction.end,
+            DexError::AuctionNotActive
+        );
+
+        let minimum_bid = match auction.highest_bid {
+            None => auction.reserve_price,
+            Some(ref highest_bid) => highest_bid.amount + auction.min_increment,
+        };
+        ensure!(amount.cmp(&minimum_bid).is_ge(), DexError::BidTooLow);
+
+        let extended_end = now
+            .checked_add(AUCTION_EXTENSION)
+            .ok_or(DexError::InvalidAuctionParams)?;
+        if extended_end > auction.end {
+            auction.end = extended_end;
+        }
+
+        auction.highest_bid.replace(Bid {
+            bidder,
+            to: params.to,
+            amount,
+        })
+    };
+
+    if let Some(previous_bid) = previous_bid {
+        refund_bid(host, &previous_bid)?;
+    }
+
+    unlock(host);
+    Ok(())
+}
+
+/// Settles an auction that has ended. Callable by anyone.
+///
+/// The token is delivered to the highest bidder and the winning bid is
+/// credited to the seller's proceeds, and its unit price is recorded like the
+/// price of a trade. If no bid reached the reserve price the token is returned to
+/// the seller. So is it if the highest bidder or the seller has been blocked
+/// since the bid, in which case the bid is refunded.
+#[receive(
+    contract = "RagnarDEX",
+    name = "settleAuction",
+    parameter = "u64",
+    enable_logger,
+    mutable
+)]
+fn settle_auction<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    lock(host)?;
+
+    let auction_id: u64 = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    let auction = host
+        .state()
+        .get_auction(auction_id)
+        .ok_or(DexError::AuctionNotFound)?;
+
+    let now = ctx.metadata().slot_time();
+    ensure!(now >= auction.end, DexError::AuctionNotEnded);
+
+    host.state_mut().remove_auction(auction_id);
+
+    let seller_blocked = host.state().is_blocked(&auction.seller);
+    let highest_bid = match auction.highest_bid {
+        Some(highest_bid)
+            if seller_blocked
+                || host.state().is_blocked(&highest_bid.bidder)
+                || host.state().is_blocked(&highest_bid.to.address()) =>
+        {
+            refund_bid(host, &highest_bid)?;
+            None
+        }
+        highest_bid => highest_bid,
+    };
+
+    match highest_bid {
+        Some(highest_bid) => {
+            let market = TokenInfo {
+                id: auction.token_id.clone(),
+                address: auction.contract,
+            };
+            let price_per_unit = unit_price(highest_bid.amount, auction.quantity);
+            record_trade(host, logger, &market, price_per_unit, now)?;
+
+            distribute_amounts(
+                host,
+                highest_bid.amount,
+                &auction.contract,
+                &auction.seller,
+                &Address::Account(ctx.owner()),
+                None,
+                now,
+            );
+
+            cis2_transfer(
+                host,
+                auction.contract,
+                auction.token_id,
+                auction.quantity,
+                Address::Contract(ctx.self_address()),
+                highest_bid.to,
+            )?;
+        }
+        None => {
+            cis2_transfer(
+                host,
+                auction.contract,
+                auction.token_id,
+                auction.quantity,
+                Address::Contract(ctx.self_address()),
+                auction.return_to,
+            )?;
+        }
+    }
+
+    unlock(host);
+    Ok(())
+}
+
+/// Refunds a bid. Accounts are paid at once, contracts are credited the bid
+/// to collect with `withdrawToContract`.
+fn refund_bid<S: HasStateApi>(
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    bid: &Bid,
+) -> ContractResult<()> {
+    match bid.bidder {
+        Address::Account(account) => host
+            .invoke_transfer(&account, bid.amount)
+            .map_err(|_| DexError::InvokeTransferError)?,
+        Address::Contract(_) => host.state_mut().credit_proceeds(&bid.bidder, bid.amount),
+    }
+    Ok(())
+}
+
+/// Returns the current state of an auction.
+#[receive(
+    contract = "RagnarDEX",
+    name = "viewAuction",
+    parameter = "u64",
+    return_value = "ContractAuctionState"
+)]
+fn view_auction<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<ContractAuctionState> {
+    let auction_id: u64 = ctx
+        .parameter_cursor()
+        .get()
+        .map_err(|_e| DexError::ParseParams)?;
+
+    host.state()
+        .get_auction(auction_id)
+        .ok_or(DexError::AuctionNotFound)
+}
+
+/// Returns `Unauthorized` unless the sender is the owner of the contract
+/// instance, under any alias.
+fn ensure_owner(ctx: &impl HasReceiveContext) -> ContractResult<()> {
+    ensure!(sender_is(ctx, &ctx.owner()), DexError::Unauthorized);
+    Ok(())
+}
+
+/// Whether the sender is `account`, or another alias of it.
+fn sender_is(ctx: &impl HasReceiveContext, account: &AccountAddress) -> bool {
+    matches!(ctx.sender(), Address::Account(sender) if sender.is_alias(account))
+}
+
+/// Returns `Unauthorized` unless the sender is the owner of the contract
+/// instance and no multisig admins are set.
+fn ensure_sole_owner<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    ensure_owner(ctx)?;
+    ensure!(host.state().admins.is_none(), DexError::Unauthorized);
+    Ok(())
+}
+
+/// Returns `Unauthorized` unless the sender is the owner of the contract
+/// instance, no multisig admins are set and the marketplace parameters have
+/// not been handed over to governance.
+fn ensure_admin<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    ensure_sole_owner(ctx, host)?;
+    ensure!(host.state().governance.is_none(), DexError::Unauthorized);
+    Ok(())
+}
+
+/// Returns the sender if it is one of the multisig signers.
+fn ensure_signer<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<AccountAddress> {
+    let admins = host
+        .state()
+        .admins
+        .as_ref()
+        .ok_or(DexError::MultisigNotConfigured)?;
+    match ctx.sender() {
+        Address::Account(account) if admins.is_signer(&account) => Ok(account),
+        _ => bail!(DexError::Unauthorized),
+    }
+}
+
+/// Returns `InvalidGovernance` if the governance parameters are invalid, and
+/// `Unauthorized` if governance is already configured.
+fn ensure_valid_governance<S: HasStateApi>(
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    governance: &ContractGovernanceConfig,
+) -> ContractResult<()> {
+    ensure!(host.state().governance.is_none(), DexError::Unauthorized);
+    ensure!(
+        governance.threshold_basis_points <= 10_000,
+        DexError::InvalidGovernance
+    );
+    Ok(())
+}
+
+/// Checks that an admin action can run. Changes to the marketplace parameters
+/// are left to governance once it is configured.
+fn ensure_valid_admin_action<S: HasStateApi>(
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    action: &ContractAdminAction,
+) -> ContractResult<()> {
+    match action {
+        // Admins can always pause trading in an emergency.
+        AdminAction::Dex(DexAction::SetPaused(true)) => Ok(()),
+        AdminAction::Dex(action) => {
+            ensure!(host.state().governance.is_none(), DexError::Unauthorized);
+            ensure_valid_action(action)
+        }
+        AdminAction::SetGovernance(governance) => ensure_valid_governance(host, governance),
+        AdminAction::SetAdmins(admins) => {
+            ensure!(admins.is_valid(), DexError::InvalidAdmins);
+            Ok(())
+        }
+        AdminAction::SetBuybackLimits(_)
+        | AdminAction::WithdrawReserve(_)
+        | AdminAction::SetAdminDelay(_)
+        | AdminAction::Upgrade(_)
+        | AdminAction::SetCompliance(_) => Ok(()),
+    }
+}
+
+/// Records the approval of `signer` for an admin proposal, and runs the action
+/// once the threshold is reached. Releases the lock.
+fn add_approval<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    proposal_id: u64,
+    signer: AccountAddress,
+) -> ContractResult<()> {
+    let mut proposal = host
+        .state()
+        .get_admin_proposal(proposal_id)
+        .ok_or(DexError::AdminActionNotFound)?;
+    ensure!(
+        !proposal.approvals.iter().any(|a| a.is_alias(&signer)),
+        DexError::AlreadyApproved
+    );
+    proposal.approvals.push(signer);
+
+    let approved = host
+        .state()
+        .admins
+        .as_ref()
+        .is_some_and(|admins| admins.is_approved(&proposal.approvals));
+    if !approved {
+        let _ = host.state_mut().admin_proposals.insert(proposal_id, proposal);
+        unlock(host);
+        return Ok(());
+    }
+
+    host.state_mut().admin_proposals.remove(&proposal_id);
+    queue_admin_action(ctx, host, proposal.action)
+}
+
+/// Queues an authorized admin action, to be run by `executeAdminAction` once
+/// the admin delay has passed. Pausing trading is exempt from the delay and
+/// runs right away. Releases the lock.
+fn queue_admin_action<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    action: ContractAdminAction,
+) -> ContractResult<()> {
+    ensure_valid_admin_action(host, &action)?;
+
+    if matches!(action, AdminAction::Dex(DexAction::SetPaused(true))) {
+        unlock(host);
+        return execute_admin_action(ctx, host, action);
+    }
+
+    let executable_at = ctx
+        .metadata()
+        .slot_time()
+        .checked_add(host.state().admin_delay)
+        .ok_or(DexError::InvalidAdminDelay)?;
+    host.state_mut().queue_admin_action(QueuedAdminAction {
+        action,
+        executable_at,
+    });
+
+    unlock(host);
+    Ok(())
+}
+
+/// Runs an approved admin action.
+fn execute_admin_action<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    action: ContractAdminAction,
+) -> ContractResult<()> {
+    match action {
+        AdminAction::Dex(action) => host.state_mut().apply_action(action),
+        AdminAction::SetGovernance(governance) => host.state_mut().governance = Some(governance),
+        AdminAction::SetBuybackLimits(limits) => host.state_mut().buyback_limits = limits,
+        AdminAction::WithdrawReserve(amount) => pay_out_reserve(ctx, host, amount)?,
+        AdminAction::SetAdmins(admins) => host.state_mut().admins = Some(admins),
+        AdminAction::SetAdminDelay(delay) => host.state_mut().admin_delay = delay,
+        AdminAction::SetCompliance(compliance) => host.state_mut().compliance = compliance,
+        AdminAction::Upgrade(params) => {
+            host.upgrade(params.module)
+                .map_err(|_e| DexError::UpgradeFailed)?;
+            if let Some((entrypoint, parameter)) = params.migrate {
+                host.invoke_contract_raw(
+                    &ctx.self_address(),
+                    parameter.as_parameter(),
+                    entrypoint.as_entrypoint_name(),
+                    Amount::zero(),
+                )
+                .map_err(|_e| DexError::UpgradeFailed)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Takes CCD out of the buyback reserve and sends it to the owner of the
+/// instance.
+fn pay_out_reserve<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    amount: Amount,
+) -> ContractResult<()> {
+    ensure!(
+        host.state().reserve.cmp(&amount).is_ge(),
+        DexError::InsufficientFunds
+    );
+    host.state_mut().reserve -= amount;
+
+    host.invoke_transfer(&ctx.owner(), amount)
+        .map_err(|_| DexError::InvokeTransferError)?;
+    Ok(())
+}
+
+/// Returns `Paused` while trading is paused.
+fn ensure_not_paused<S: HasStateApi>(
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    ensure!(!host.state().paused, DexError::Paused);
+    Ok(())
+}
+
+/// Returns `InvalidFees` if a fee is above 100%.
+fn ensure_valid_fees(fees: &FeeConfig) -> ContractResult<()> {
+    ensure!(
+        fees.commission_basis_points <= 10_000 && fees.referral_share_basis_points <= 10_000,
+        DexError::InvalidFees
+    );
+    Ok(())
+}
+
+/// Checks the parameters of an action before it is proposed.
+fn ensure_valid_action(action: &DexAction) -> ContractResult<()> {
+    match action {
+        DexAction::SetFees(fees) => ensure_valid_fees(fees),
+        _ => Ok(()),
+    }
+}
+
+/// Queries the governance token for the balance `voter` held just before
+/// `snapshot`.
+fn voting_weight<S: HasStateApi>(
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    governance: &ContractGovernanceConfig,
+    voter: AccountAddress,
+    snapshot: Timestamp,
+) -> ContractResult<ContractTokenAmount> {
+    let params = BalanceOfAtQueryParams {
+        queries: vec![BalanceOfAtQuery {
+            token_id: governance.token_id.clone(),
+            address: Address::Account(voter),
+            time: snapshot,
+        }],
+    };
+
+    let mut response = host
+        .invoke_contract_read_only(
+            &governance.token,
+            &params,
+            EntrypointName::new_unchecked("balanceOfAt"),
+            Amount::zero(),
+        )
+        .map_err(|_e| DexError::Cis2ClientError)?
+        .ok_or(DexError::Cis2ClientError)?;
+    let balances: BalanceOfQueryResponse<ContractTokenAmount> =
+        response.get().map_err(|_e| DexError::Cis2ClientError)?;
+
+    balances.0.first().copied().ok_or(DexError::Cis2ClientError)
+}
+
+/// Whether a proposal reached the quorum, and its share of votes in favour is
+/// above the threshold.
+fn has_passed(proposal: &ContractProposal, governance: &ContractGovernanceConfig) -> bool {
+    let votes_cast = proposal.votes_for + proposal.votes_against;
+    let votes_for = proposal.votes_for.0.saturating_mul(10_000u64.into());
+    let required = votes_cast
+        .0
+        .saturating_mul(u64::from(governance.threshold_basis_points).into());
+
+    votes_cast >= governance.quorum && votes_for > required
+}
+
+/// Returns `InvalidReferrer` if a trade names its own sender or seller as the
+/// referrer, under any alias.
+fn ensure_valid_referrer(
+    ctx: &impl HasReceiveContext,
+    params: &TransferParams,
+) -> ContractResult<()> {
+    if let Some(referrer) = params.referrer {
+        ensure!(
+            !is_alias_of(&params.owner, &referrer) && !sender_is(ctx, &referrer),
+            DexError::InvalidReferrer
+        );
+    }
+    Ok(())
+}
+
+/// Checks the identity claims of `trader` against the identity policies of the
+/// marketplace, of the collection and of the token. Claims given with the call
+/// have to be about the trader and signed by a trusted verifier. Without them,
+/// the claims posted to the attestation registry are used.
+fn ensure_identity<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    market: &TokenInfo<ContractTokenId>,
+    trader: &Address,
+    attestation: &Option<SignedAttestation>,
+    crypto_primitives: &impl HasCryptoPrimitives,
+) -> ContractResult<()> {
+    let now = ctx.metadata().slot_time();
+    let state = host.state();
+    let attestation = match attestation {
+        Some(attestation) if !state.identity_policies_of(market).is_empty() => attestation,
+        _ => return Ok(state.check_eligibility(market, trader, now)?),
+    };
+
+    let claims = &attestation.claims;
+    ensure!(
+        state.is_verifier(&attestation.verifier),
+        DexError::UnknownVerifier
+    );
+    ensure!(
+        canonical_address(&claims.subject) == canonical_address(trader),
+        DexError::InvalidAttestation
+    );
+    let message = signed_message_hash(crypto_primitives, &attestation.verifier, &to_bytes(claims));
+    let signed =
+        host.check_account_signature(attestation.verifier, &attestation.signatures, &message.0);
+    ensure!(matches!(signed, Ok(true)), DexError::InvalidAttestation);
+    Ok(state.check_claims(market, &attestation.verifier, claims, now)?)
+}
+
+/// Hash of `message` as signed by `signer` with a wallet. Wallets prefix the
+/// message with the address of the signer and eight zero bytes, so that a
+/// signed message can never be mistaken for a signed transaction.
+fn signed_message_hash(
+    crypto_primitives: &impl HasCryptoPrimitives,
+    signer: &AccountAddress,
+    message: &[u8],
+) -> HashSha2256 {
+    let mut prefixed = signer.0.to_vec();
+    prefixed.extend_from_slice(&[0u8; 8]);
+    prefixed.extend_from_slice(message);
+    crypto_primitives.hash_sha2_256(&prefixed)
+}
+
+/// Whether `address` is an alias of `account`. Contract addresses are not
+/// aliases of any account.
+fn is_alias_of(address: &Address, account: &AccountAddress) -> bool {
+    matches!(address, Address::Account(a) if a.is_alias(account))
+}
+
+/// Returns `CalledByAContract` if `address` is a contract and the marketplace
+/// does not allow contract principals. Age verification is tied to the
+/// identity behind an account, so a contract can only list or receive tokens
+/// once the admins have accepted that risk with `setContractPrincipals`.
+fn ensure_principal_allowed<S: HasStateApi>(
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    address: &Address,
+) -> ContractResult<()> {
+    if let Address::Contract(_) = address {
+        ensure!(
+            host.state().allow_contract_principals,
+            DexError::CalledByAContract
+        );
+    }
+    Ok(())
+}
+
+/// Returns `MarketHalted` while the circuit breaker of the market is tripped.
+fn ensure_market_open<S: HasStateApi>(
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    market: &TokenInfo<ContractTokenId>,
+    now: Timestamp,
+) -> ContractResult<()> {
+    ensure!(
+        !host.state().is_halted(market, now),
+        DexError::MarketHalted
+    );
+    Ok(())
+}
+
+/// Records the CCD unit price of a trade in the price history and for the
+/// circuit breaker of its market, and logs `MarketHalted` if the trade trips
+/// the breaker. The trade itself goes through, as rejecting it would also roll
+/// back the halt.
+fn record_trade<S: HasStateApi>(
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+    market: &TokenInfo<ContractTokenId>,
+    price: Amount,
+    now: Timestamp,
+) -> ContractResult<()> {
+    host.state_mut().observe_price(market, price, now);
+    let tripped = host.state_mut().record_trade_price(market, price, now);
+    if let Some(MarketBreaker {
+        reference_price,
+        halted_until: Some(halted_until),
+        ..
+    }) = tripped
+    {
+        logger.log(&DexEvent::MarketHalted(MarketHaltedEvent {
+            token_id: market.id.clone(),
+            contract: market.address,
+            price,
+            reference_price,
+            halted_until,
+        }))?;
+    }
+    Ok(())
+}
+
+/// Returns `Blocked` if any of the addresses is on the blocklist.
+fn ensure_not_blocked<S: HasStateApi>(
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    addresses: &[Address],
+) -> ContractResult<()> {
+    ensure!(
+        !addresses
+            .iter()
+            .any(|address| host.state().is_blocked(address)),
+        DexError::Blocked
+    );
+    Ok(())
+}
+
+/// Returns `Unauthorized` unless the sender is the owner of the collection
+/// recorded at registration.
+fn ensure_collection_owner<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    cis_contract_address: &ContractAddress,
+) -> ContractResult<()> {
+    let collection_owner = host
+        .state()
+        .collections
+        .get(cis_contract_address)
+        .and_then(|info| info.owner)
+        .ok_or(DexError::Unauthorized)?;
+    ensure!(sender_is(ctx, &collection_owner), DexError::Unauthorized);
+    Ok(())
+}
+
+/// Returns `Unauthorized` unless the sender is the compliance account.
+fn ensure_compliance<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let compliance = host.state().compliance.ok_or(DexError::Unauthorized)?;
+    ensure!(sender_is(ctx, &compliance), DexError::Unauthorized);
+    Ok(())
+}
+
+/// Returns `CollectionNotApproved` unless the CIS2 contract is in the
+/// collection registry, or the registry is in permissionless mode.
+fn ensure_collection_allowed<S: HasStateApi>(
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    cis_contract_address: &ContractAddress,
+) -> ContractResult<()> {
+    ensure!(
+        host.state().is_collection_allowed(cis_contract_address),
+        DexError::CollectionNotApproved
+    );
+    Ok(())
+}
+
+/// Takes the reentrancy lock of the Dex.
+///
+/// Every mutating entrypoint takes the lock before anything else and releases
+/// it with [`unlock`] just before returning. The lock is part of the state, so
+/// a CIS2 contract which calls back into the Dex in the middle of a trade
+/// finds it taken and is rejected with `Reentrancy`. A rejected call rolls
+/// back its state, so the lock never needs releasing on an error path.
+fn lock<S: HasStateApi>(
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    ensure!(!host.state().locked, DexError::Reentrancy);
+    host.state_mut().locked = true;
+    Ok(())
+}
+
+/// Releases the reentrancy lock taken by [`lock`].
+fn unlock<S: HasStateApi>(host: &mut impl HasHost<ContractState<S>, StateApiType = S>) {
+    host.state_mut().locked = false;
+}
+
+/// Calls the [supports](https://proposals.concordium.software/CIS/cis-0.html#supports) function of CIS2 contract.
+/// Returns error If the contract does not support the standard.
+fn ensure_supports_cis2<S: HasStateApi, T: IsTokenId + Clone, A: IsTokenAmount + Copy>(
+    host: &mut impl HasHost<State<S, T, A>, StateApiType = S>,
+    cis_contract_address: &ContractAddress,
+) -> ContractResult<()> {
+    let cis2_client = Cis2Client::new(*cis_contract_address);
+    let res: Cis2ClientResult<SupportResult> = cis2_client.supports_cis2(host);
+
+    let res = match res {
+        Ok(res) => res,
+        Err(_) => bail!(DexError::Cis2ClientError),
+    };
+
+    match res {
+        SupportResult::NoSupport => bail!(DexError::CollectionNotCis2),
+        SupportResult::SupportBy(_) => Ok(()),
+        SupportResult::Support => Ok(()),
+    }
+}
+
+/// Calls the [operatorOf](https://proposals.concordium.software/CIS/cis-2.html#operatorof) function of CIS contract.
+/// Returns error if Current Contract Address is not an Operator of Transaction
+/// Sender.
+fn ensure_is_operator<S: HasStateApi, T: IsTokenId + Clone, A: IsTokenAmount + Copy>(
+    host: &mut impl HasHost<State<S, T, A>, StateApiType = S>,
+    ctx: &impl HasReceiveContext<()>,
+    cis_contract_address: &ContractAddress,
+) -> ContractResult<()> {
+    let cis2_client = Cis2Client::new(*cis_contract_address);
+    let res: Cis2ClientResult<bool> =
+        cis2_client.operator_of(host, ctx.sender(), Address::Contract(ctx.self_address()));
+    let res = match res {
+        Ok(res) => res,
+        Err(_) => bail!(DexError::Cis2ClientError),
+    };
+    ensure!(res, DexError::NotOperator);
+    Ok(())
+}
+
+/// Calls the [balanceOf](https://proposals.concordium.software/CIS/cis-2.html#balanceof) function of the CIS2 contract.
+/// Returns error if the returned balance < input balance (balance param).
+fn ensure_balance<S: HasStateApi, T: IsTokenId + Clone, A: IsTokenAmount + Ord + Copy>(
+    host: &mut impl HasHost<State<S, T, A>, StateApiType = S>,
+    token_id: T,
+    cis_contract_address: &ContractAddress,
+    owner: Address,
+    minimum_balance: A,
+) -> ContractResult<()> {
+    let cis2_client = Cis2Client::new(*cis_contract_address);
+
+    let res: Cis2ClientResult<A> = cis2_client.balance_of(host, token_id, owner);
+    let res = match res {
+        Ok(res) => res,
+        Err(_) => bail!(DexError::Cis2ClientError),
+    };
+    ensure!(
+        res.cmp(&minimum_balance).is_ge(),
+        DexError::NoBalance
+    );
+
+    Ok(())
+}
+
+/// Checks that the seller of a listing still backs it, by calling the
+/// `operatorOf` and `balanceOf` functions of the CIS2 contract.
+/// Returns `OperatorRevoked` if the Dex is no longer an operator of the seller
+/// and `SellerBalanceGone` if the seller holds less than `quantity`.
+fn ensure_listing_backed<S: HasStateApi>(
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    self_address: ContractAddress,
+    token_info: &TokenInfo<ContractTokenId>,
+    owner: Address,
+    quantity: ContractTokenAmount,
+) -> ContractResult<()> {
+    let cis2_client = Cis2Client::new(token_info.address);
+
+    let res: Cis2ClientResult<bool> =
+        cis2_client.operator_of(host, owner, Address::Contract(self_address));
+    let is_operator = match res {
+        Ok(res) => res,
+        Err(_) => bail!(DexError::Cis2ClientError),
+    };
+    ensure!(is_operator, DexError::OperatorRevoked);
+
+    let res: Cis2ClientResult<ContractTokenAmount> =
+        cis2_client.balance_of(host, token_info.id.clone(), owner);
+    let balance = match res {
+        Ok(res) => res,
+        Err(_) => bail!(DexError::Cis2ClientError),
+    };
+    ensure!(
+        balance.cmp(&quantity).is_ge(),
+        DexError::SellerBalanceGone
+    );
+
+    Ok(())
+}
+
+/// Price of a single unit of a listed token at time `now`, in CCD. Prices of
+/// euro listings are converted at the current exchange rate of the chain.
+fn unit_price_ccd<S: HasStateApi>(
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    listed_token: &TokenPriceState<ContractTokenAmount>,
+    now: Timestamp,
+) -> ContractResult<Amount> {
+    let unit_price = listing_unit_price(host, listed_token, now)?;
+    match listed_token.currency {
+        Currency::Ccd => Ok(unit_price),
+        Currency::Eur => euro_to_ccd(
+            unit_price.micro_ccd,
+            host.exchange_rates().micro_ccd_per_euro,
+        )
+        .ok_or(DexError::PriceOverflow),
+    }
+}
+
+/// Price of a single unit of a listed token at time `now`, in micro-units of
+/// its currency. Oracle listings are priced from their feed, and rejected with
+/// `StalePrice` if the feed is too old.
+fn listing_unit_price<S: HasStateApi>(
+    host: &impl HasHost<ContractState<S>, StateApiType = S>,
+    listed_token: &TokenPriceState<ContractTokenAmount>,
+    now: Timestamp,
+) -> ContractResult<Amount> {
+    let (oracle, feed_id, adjustment_basis_points, max_age) = match listed_token.mode {
+        PriceMode::Oracle {
+            oracle,
+            feed_id,
+            adjustment_basis_points,
+            max_age,
+        } => (oracle, feed_id, adjustment_basis_points, max_age),
+        _ => return Ok(listed_token.unit_price(now)),
+    };
+
+    let mut response = host
+        .invoke_contract_read_only(
+            &oracle,
+            &feed_id,
+            EntrypointName::new_unchecked("getPrice"),
+            Amount::zero(),
+        )
+        .map_err(|_e| DexError::OracleError)?
+        .ok_or(DexError::OracleError)?;
+    let feed: OraclePrice = response.get().map_err(|_e| DexError::OracleError)?;
+
+    ensure!(
+        !matches!(now.duration_since(feed.updated_at), Some(age) if age > max_age),
+        DexError::StalePrice
+    );
+    adjust_price(feed.price, adjustment_basis_points).ok_or(DexError::PriceOverflow)
+}
+
+/// Price of `quantity` units at `unit_price` each. Returns
+/// `InvalidTokenQuantity` if the quantity does not fit in a `u64`, and
+/// `PriceOverflow` if the price does not fit in an `Amount`.
+fn total_price(unit_price: Amount, quantity: ContractTokenAmount) -> ContractResult<Amount> {
+    ensure!(quantity.0.bits() <= 64, DexError::InvalidTokenQuantity);
+    unit_price
+        .micro_ccd
+        .checked_mul(quantity.0.low_u64())
+        .map(Amount::from_micro_ccd)
+        .ok_or(DexError::PriceOverflow)
+}
+
+/// Price of one unit when `quantity` units sell for `price`, rounded down.
+fn unit_price(price: Amount, quantity: ContractTokenAmount) -> Amount {
+    let quantity = if quantity.0.bits() <= 64 {
+        quantity.0.low_u64()
+    } else {
+        u64::MAX
+    };
+    Amount::from_micro_ccd(price.micro_ccd / quantity.max(1))
+}
+
+/// Hash of an order, under which its fills are recorded. The address of the
+/// Dex is hashed along with the order, like it is signed along with it, so
+/// that the order cannot be filled on another instance.
+fn order_hash(
+    ctx: &impl HasReceiveContext,
+    crypto_primitives: &impl HasCryptoPrimitives,
+    order: &Order,
+) -> HashSha2256 {
+    crypto_primitives.hash_sha2_256(&order_message(ctx, order))
+}
+
+/// The message the maker of an order signs with a wallet: the address of the
+/// Dex followed by the serialized order.
+fn order_message(ctx: &impl HasReceiveContext, order: &Order) -> Vec<u8> {
+    let mut message = to_bytes(&ctx.self_address());
+    message.extend(to_bytes(order));
+    message
+}
+
+/// Calls the [transfer](https://proposals.concordium.software/CIS/cis-2.html#transfer) function of the CIS2 contract.
+/// If the contract delegates CIS2 to another implementor, the transfer is sent
+/// to that implementor instead.
+fn cis2_transfer<S: HasStateApi>(
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    cis_contract_address: ContractAddress,
+    token_id: ContractTokenId,
+    amount: ContractTokenAmount,
+    from: Address,
+    to: Receiver,
+) -> ContractResult<()> {
+    let cis2_client = Cis2Client::new(cis_contract_address);
+    let res: Cis2ClientResult<SupportResult> = cis2_client.supports_cis2(host);
+    let res = match res {
+        Ok(res) => res,
+        Err(_) => bail!(DexError::Cis2ClientError),
+    };
+    // Checks if the CIS2 contract supports the CIS2 interface.
+    let cis2_contract_address = match res {
+        SupportResult::NoSupport => bail!(DexError::CollectionNotCis2),
+        SupportResult::Support => cis_contract_address,
+        SupportResult::SupportBy(contracts) => match contracts.first() {
+            Some(c) => *c,
+            None => bail!(DexError::CollectionNotCis2),
+        },
+    };
+
+    let cis2_client = Cis2Client::new(cis2_contract_address);
+    let res: Cis2ClientResult<bool> = cis2_client.transfer(
+        host,
+        Transfer {
+            amount,
+            from,
+            to,
+            token_id,
+            data: AdditionalData::empty(),
+        },
+    );
+
+    match res {
+        Ok(_) => Ok(()),
+        Err(_) => bail!(DexError::Cis2ClientError),
+    }
+}
+
+/// The receive hook of this contract, used when the Dex itself takes custody
+/// of CIS2 tokens.
+fn self_receiver(ctx: &impl HasReceiveContext) -> Receiver {
+    Receiver::Contract(
+        ctx.self_address(),
+        OwnedEntrypointName::new_unchecked("receive_ccd".to_string()),
+    )
+}
+
+
+struct DistributableAmounts {
+    to_primary_owner: Amount,
+    to_royalty_recipient: Amount,
+    to_marketplace_owner: Amount,
+    to_referrer: Amount,
+}
+
+/// Credits the proceeds of a sale to the withdrawable balances of everyone
+/// owed a share of it. The balances are paid out by `withdraw`.
+fn distribute_amounts<S: HasStateApi>(
+    host: &mut impl HasHost<ContractState<S>, StateApiType = S>,
+    amount: Amount,
+    cis_contract_address: &ContractAddress,
+    token_owner: &Address,
+    marketplace_owner: &Address,
+    referrer: Option<AccountAddress>,
+    now: Timestamp,
+) {
+    let royalty = host.state().royalty_of(cis_contract_address, now);
+    let amounts = calculate_amounts(
+        &amount,
+        &host.state().fees,
+        royalty.map_or(0, |r| r.basis_points),
+        referrer.is_some(),
+    );
+
+    // The shares of a blocked royalty recipient or referrer go to the
+    // marketplace instead.
+    let state = host.state_mut();
+    state.credit_proceeds(token_owner, amounts.to_primary_owner);
+    state.credit_proceeds(marketplace_owner, amounts.to_marketplace_owner);
+    if let Some(royalty) = royalty {
+        let recipient = Address::Account(royalty.recipient);
+        if state.is_blocked(&recipient) {
+            state.credit_proceeds(marketplace_owner, amounts.to_royalty_recipient);
+        } else {
+            state.credit_proceeds(&recipient, amounts.to_royalty_recipient);
+        }
+    }
+    if let Some(referrer) = referrer {
+        if state.is_blocked(&Address::Account(referrer)) {
+            state.credit_proceeds(marketplace_owner, amounts.to_referrer);
+        } else {
+            state.credit_referral(&referrer, amounts.to_referrer);
+        }
+    }
+}
+
+/// Calculates the amounts (Commission, Royalty & Selling Price) to be
+/// distributed
+fn calculate_amounts(
+    amount: &Amount,
+    fees: &FeeConfig,
+    royalty_basis_points: u16,
+    has_referrer: bool,
+) -> DistributableAmounts {
+    let commission = basis_points_of(amount, fees.commission_basis_points);
+    let to_referrer = if has_referrer {
+        basis_points_of(&commission, fees.referral_share_basis_points)
+    } else {
+        Amount::zero()
+    };
+    // The royalty is capped so that commission and royalty never exceed the
+    // sale price.
+    let to_royalty_recipient =
+        basis_points_of(amount, royalty_basis_points).min(*amount - commission);
+
+    DistributableAmounts {
+        to_primary_owner: *amount - commission - to_royalty_recipient,
+        to_royalty_recipient,
+        to_marketplace_owner: commission - to_referrer,
+        to_referrer,
+    }
+}
+
+/// Share of `amount` given in basis points, rounded down.
+fn basis_points_of(amount: &Amount, basis_points: u16) -> Amount {
+    let share = u128::from(amount.micro_ccd) * u128::from(basis_points) / 10_000;
+    Amount::from_micro_ccd(share as u64)
+}
+
+#[concordium_cfg_test]
+#[allow(deprecated)]
+mod test {
+    use crate::{
+        add, approve_admin_action, bid, block, calculate_amounts, cancel_admin_action,
+        cancel_orders, create_auction, delist_stale, distribute_amounts,
+        errors::DexError,
+        events::{BlocklistEvent, DelistReason, DexEvent, ListingRemovedEvent, MarketHaltedEvent},
+        execute_proposal, execute_queued_admin_action, fill_order, is_blocked, list,
+        parameter::{
+            AddParams, BidParams, CreateAuctionParams, FillOrderParams, ListingParams, OraclePrice,
+            Order, PruneParams, SetRoyaltyParams, SetTokenPolicyParams, SignedAttestation,
+            TransferParams, VoteParams,
+        },
+        post_attestation, propose, propose_admin_action, prune_expired, revoke_attestation,
+        set_fees, set_paused, set_royalty, set_token_policy, settle_auction,
+        state::{
+            canonical_address, euro_to_ccd, AdminAction, AuctionState, BuybackLimits,
+            CircuitBreakerConfig, CollectionInfo, Currency, DexAction,
+            FeeConfig, GovernanceConfig, IdentityClaims, IdentityPolicy, IneligibleReason,
+            MultisigConfig, PriceMode, PruneResult, Royalty, RoyaltyChange, State, TokenInfo,
+            TokenOwnerInfo, TokenPriceState,
+        },
+        total_price, transfer, transfer_cis2, unblock, vote, withdraw, ContractState,
+        ContractTokenAmount, ContractTokenId,
+    };
+    use concordium_cis2::*;
+
+    use concordium_std::{test_infrastructure::*, *};
+    use std::{cell::Cell, rc::Rc};
+
+    const ACCOUNT_0: AccountAddress = AccountAddress([0u8; 32]);
+    const ADDRESS_0: Address = Address::Account(ACCOUNT_0);
+    const ACCOUNT_1: AccountAddress = AccountAddress([1u8; 32]);
+    const ADDRESS_1: Address = Address::Account(ACCOUNT_1);
+    const CIS_CONTRACT_ADDRESS: ContractAddress = ContractAddress {
+        index: 1,
+        subindex: 0,
+    };
+    const MARKET_CONTRACT_ADDRESS: ContractAddress = ContractAddress {
+        index: 2,
+        subindex: 0,
+    };
+    const GOVERNANCE_TOKEN_ADDRESS: ContractAddress = ContractAddress {
+        index: 3,
+        subindex: 0,
+    };
+
+    fn token_id(id: u8) -> ContractTokenId { TokenIdVec(vec![id]) }
+
+    fn token_amount(amount: u64) -> ContractTokenAmount { TokenAmountU256(amount.into()) }
+
+    #[concordium_test]
+    fn should_add_token() {
+        let token_id_1 = token_id(1);
+        let token_quantity_1 = token_amount(1);
+        let price = Amount::from_ccd(1);
+
+        let mut ctx = TestReceiveContext::default();
+        ctx.set_sender(ADDRESS_0);
+        ctx.set_self_address(MARKET_CONTRACT_ADDRESS);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
+
+        let add_params = AddParams {
+            cis_contract_address: CIS_CONTRACT_ADDRESS,
+            price,
+            currency: Currency::Ccd,
+            mode: PriceMode::Fixed,
+            token_id: token_id_1.clone(),
+            quantity: token_quantity_1,
+            expires_at: None,
+            attestation: None,
+        };
+        let parameter_bytes = to_bytes(&add_params);
+        ctx.set_parameter(&parameter_bytes);
+
+        let mut state_builder = TestStateBuilder::new();
+        let mut state = State::new(&mut state_builder);
+        approve_collection(&mut state);
+        let mut host = TestHost::new(state, state_builder);
+
+        fn mock_supports(
+            _p: Parameter,
+            _a: Amount,
+            _a2: &mut Amount,
+            _s: &mut ContractState<TestStateApi>,
+        ) -> Result<(bool, SupportsQueryResponse), CallContractError<SupportsQueryResponse>>
+        {
+            Ok((
+                false,
+                SupportsQueryResponse {
+                    results: vec![SupportResult::Support],
+                },
+            ))
+        }
+
+        fn mock_is_operator_of(
+            _p: Parameter,
+            _a: Amount,
+            _a2: &mut Amount,
+            _s: &mut ContractState<TestStateApi>,
+        ) -> Result<(bool, OperatorOfQueryResponse), CallContractError<OperatorOfQueryResponse>>
+        {
+            Ok((false, OperatorOfQueryResponse(vec![true])))
+        }
+
+        fn mock_balance_of(
+            _p: Parameter,
+            _a: Amount,
+            _a2: &mut Amount,
+            _s: &mut ContractState<TestStateApi>,
+        ) -> Result<
+            (bool, BalanceOfQueryResponse<ContractTokenAmount>),
+            CallContractError<BalanceOfQueryResponse<ContractTokenAmount>>,
+        > {
+            Ok((false, BalanceOfQueryResponse(vec![token_amount(1)])))
+        }
+
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("supports".to_string()),
+            MockFn::new_v1(mock_supports),
+        );
+
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("operatorOf".to_string()),
+            MockFn::new_v1(mock_is_operator_of),
+        );
+
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("balanceOf".to_string()),
+            MockFn::new_v1(mock_balance_of),
+        );
+
+        let res = add(&ctx, &mut host, &crypto());
+
+        claim!(res.is_ok(), "Results in rejection");
+        claim!(
+            host.state().token_prices.iter().count() != 0,
+            "Token not added"
+        );
+        
+        
+
+        let listed_token = host
+            .state()
+            .get_token(
+                &TokenInfo {
+                    id: token_id_1,
+                    address: CIS_CONTRACT_ADDRESS,
+                },
+                &ADDRESS_0,
+            )
+            .expect("Should not be None");
+
+        claim_eq!(
+            listed_token,
+            TokenPriceState {
+                price,
+                currency: Currency::Ccd,
+                mode: PriceMode::Fixed,
+                quantity: token_quantity_1,
+                listed_at: Timestamp::from_timestamp_millis(0),
+                expires_at: None,
+                holder: ADDRESS_0,
+            },
+        )
+    }
+
+    #[concordium_test]
+    fn dutch_price_decays_to_end_price() {
+        let listed_token = TokenPriceState {
+            price: Amount::from_ccd(100),
+            currency: Currency::Ccd,
+            mode: PriceMode::Dutch {
+                end_price: Amount::from_ccd(20),
+                duration: Duration::from_seconds(100),
+            },
+            quantity: token_amount(10),
+            listed_at: Timestamp::from_timestamp_millis(1_000),
+            expires_at: None,
+            holder: ADDRESS_0,
+        };
+
+        claim_eq!(
+            listed_token.unit_price(Timestamp::from_timestamp_millis(1_000)),
+            Amount::from_ccd(100)
+        );
+        claim_eq!(
+            listed_token.unit_price(Timestamp::from_timestamp_millis(51_000)),
+            Amount::from_ccd(60)
+        );
+        claim_eq!(
+            listed_token.unit_price(Timestamp::from_timestamp_millis(500_000)),
+            Amount::from_ccd(20)
+        );
+    }
+
+    #[concordium_test]
+    fn expired_listings_cannot_be_bought_and_are_pruned() {
+        let mut host = host_with_listing();
+        let listing = |expires_at| TokenPriceState {
+            price: Amount::from_ccd(1),
+            currency: Currency::Ccd,
+            mode: PriceMode::Fixed,
+            quantity: token_amount(1),
+            listed_at: Timestamp::from_timestamp_millis(0),
+            expires_at: Some(Timestamp::from_timestamp_millis(expires_at)),
+            holder: ADDRESS_1,
+        };
+        for (id, expires_at) in [(2, 10), (3, 100), (4, 10)] {
+            let token_info = TokenInfo {
+                id: token_id(id),
+                address: CIS_CONTRACT_ADDRESS,
+            };
+            host.state_mut().list_token(&token_info, &ADDRESS_1, listing(expires_at));
+        }
+
+        let parameter_bytes = to_bytes(&TransferParams {
+            token_id: token_id(2),
+            owner: ADDRESS_1,
+            to: Receiver::Account(ACCOUNT_0),
+            ..buy_params()
+        });
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                transfer(
+                    &admin_context(ADDRESS_0, 10, &parameter_bytes),
+                    host,
+                    Amount::from_ccd(1),
+                    &mut TestLogger::init(),
+                    &crypto(),
+                )
+            }),
+            Err(DexError::TokenNotListed)
+        );
+        let listed = list(&admin_context(ADDRESS_0, 10, &[]), &host).expect("Listing fails");
+        claim_eq!(
+            listed.0.iter().map(|item| item.token_id.clone()).collect::<Vec<_>>(),
+            vec![token_id(1), token_id(3)]
+        );
+
+        // The listing without an expiry is never scanned. Scanning stops after
+        // the given number of listings.
+        let mut prune = |millis, start, max_scanned| {
+            let parameter_bytes = to_bytes(&PruneParams {
+                start,
+                max_scanned,
+            });
+            prune_expired(&admin_context(ADDRESS_0, millis, &parameter_bytes), &mut host)
+        };
+        claim_eq!(
+            prune(50, 0, 2),
+            Ok(PruneResult {
+                removed: 1,
+                next: Some(2),
+            })
+        );
+        claim_eq!(
+            prune(50, 2, 2),
+            Ok(PruneResult {
+                removed: 1,
+                next: None,
+            })
+        );
+        claim_eq!(
+            prune(50, 0, 10),
+            Ok(PruneResult {
+                removed: 0,
+                next: None,
+            })
+        );
+        claim_eq!(
+            prune(100, 0, 10),
+            Ok(PruneResult {
+                removed: 1,
+                next: None,
+            })
+        );
+        claim_eq!(host.state().token_prices.iter().count(), 1);
+        claim_eq!(host.state().expiring_listings.iter().count(), 0);
+    }
+
+    fn approve_collection(state: &mut ContractState<TestStateApi>) {
+        let _ = state.collections.insert(CIS_CONTRACT_ADDRESS, CollectionInfo {
+            name: None,
+            symbol: None,
+            decimals: None,
+            owner: None,
+        });
+    }
+
+    #[concordium_test]
+    fn royalty_is_capped_and_raises_wait_for_the_admin_delay() {
+        let mut host = host_with_listing();
+        host.state_mut()
+            .collections
+            .get_mut(&CIS_CONTRACT_ADDRESS)
+            .expect("Collection is not registered")
+            .owner = Some(ACCOUNT_1);
+        let royalty = |basis_points| SetRoyaltyParams {
+            cis_contract_address: CIS_CONTRACT_ADDRESS,
+            royalty: Some(Royalty {
+                basis_points,
+                recipient: ACCOUNT_1,
+            }),
+        };
+        let delay = host.state().admin_delay.millis();
+
+        let parameter_bytes = to_bytes(&royalty(1_001));
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                set_royalty(&admin_context(ADDRESS_1, 0, &parameter_bytes), host)
+            }),
+            Err(DexError::InvalidRoyalty)
+        );
+
+        let parameter_bytes = to_bytes(&royalty(1_000));
+        let ctx = admin_context(ADDRESS_1, 0, &parameter_bytes);
+        claim!(set_royalty(&ctx, &mut host).is_ok(), "Setting the royalty is rejected");
+        let state = host.state();
+        let raised = Royalty {
+            basis_points: 1_000,
+            recipient: ACCOUNT_1,
+        };
+        claim_eq!(
+            state.royalty_of(&CIS_CONTRACT_ADDRESS, Timestamp::from_timestamp_millis(0)),
+            None
+        );
+        claim_eq!(
+            state.royalty_change_of(&CIS_CONTRACT_ADDRESS, Timestamp::from_timestamp_millis(0)),
+            Some(RoyaltyChange {
+                royalty: raised,
+                effective_at: Timestamp::from_timestamp_millis(delay),
+            })
+        );
+        claim_eq!(
+            state.royalty_of(&CIS_CONTRACT_ADDRESS, Timestamp::from_timestamp_millis(delay)),
+            Some(raised)
+        );
+
+        // Lowering the royalty applies at once.
+        let parameter_bytes = to_bytes(&royalty(100));
+        let ctx = admin_context(ADDRESS_1, delay, &parameter_bytes);
+        claim!(set_royalty(&ctx, &mut host).is_ok(), "Lowering the royalty is rejected");
+        claim_eq!(
+            host.state().royalty_of(&CIS_CONTRACT_ADDRESS, Timestamp::from_timestamp_millis(delay)),
+            Some(Royalty {
+                basis_points: 100,
+                recipient: ACCOUNT_1,
+            })
+        );
+    }
+
+    #[concordium_test]
+    fn euro_price_converts_to_ccd() {
+        // 1 euro is 20 CCD.
+        let micro_ccd_per_euro = ExchangeRate::new_unchecked(20_000_000, 1);
+
+        claim_eq!(
+            euro_to_ccd(2_500_000, micro_ccd_per_euro),
+            Some(Amount::from_ccd(50))
+        );
+        // Rounds up to the nearest microCCD.
+        claim_eq!(
+            euro_to_ccd(1, ExchangeRate::new_unchecked(1, 3)),
+            Some(Amount::from_micro_ccd(1))
+        );
+    }
+
+    #[concordium_test]
+    fn total_price_rejects_overflow() {
+        claim_eq!(
+            total_price(Amount::from_ccd(2), token_amount(3)),
+            Ok(Amount::from_ccd(6))
+        );
+        // Wraps to 2 microCCD without the overflow check.
+        claim_eq!(
+            total_price(Amount::from_micro_ccd(1 << 63 | 1), token_amount(2)),
+            Err(DexError::PriceOverflow)
+        );
+        claim_eq!(
+            total_price(
+                Amount::from_micro_ccd(1),
+                token_amount(u64::MAX) + token_amount(1)
+            ),
+            Err(DexError::InvalidTokenQuantity)
+        );
+    }
+
+    #[concordium_test]
+    fn sale_price_is_split_between_fees_royalty_and_seller() {
+        let fees = FeeConfig {
+            commission_basis_points: 500,
+            referral_share_basis_points: 2_000,
+        };
+        let amounts = calculate_amounts(&Amount::from_ccd(200), &fees, 250, true);
+
+        claim_eq!(amounts.to_marketplace_owner, Amount::from_ccd(8));
+        claim_eq!(amounts.to_referrer, Amount::from_ccd(2));
+        claim_eq!(amounts.to_royalty_recipient, Amount::from_ccd(5));
+        claim_eq!(amounts.to_primary_owner, Amount::from_ccd(185));
+    }
+
+    /// Mock entrypoint returning the value built by `response` on every call.
+    fn returning<R: Serial + 'static>(
+        response: impl Fn() -> R + 'static,
+    ) -> MockFn<ContractState<TestStateApi>> {
+        MockFn::new_v1(move |_parameter, _amount, _balance, _state| Ok((false, response())))
+    }
+
+    /// Crypto primitives for entrypoints which check signatures.
+    fn crypto() -> TestCryptoPrimitives {
+        TestCryptoPrimitives::new()
+    }
+
+    /// Host with 2 units of token 1 listed by ACCOUNT_0 at 1 CCD each, and a
+    /// CIS2 contract which reports ACCOUNT_0 as backing the listing.
+    fn host_with_listing() -> TestHost<ContractState<TestStateApi>> {
+        let mut state_builder = TestStateBuilder::new();
+        let mut state = State::new(&mut state_builder);
+        approve_collection(&mut state);
+        state.list_token(
+            &TokenInfo {
+                id: token_id(1),
+                address: CIS_CONTRACT_ADDRESS,
+            },
+            &ADDRESS_0,
+            TokenPriceState {
+                price: Amount::from_ccd(1),
+                currency: Currency::Ccd,
+                mode: PriceMode::Fixed,
+                quantity: token_amount(2),
+                listed_at: Timestamp::from_timestamp_millis(0),
+                expires_at: None,
+                holder: ADDRESS_0,
+            },
+        );
+        let mut host = TestHost::new(state, state_builder);
+
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("supports".to_string()),
+            returning(|| SupportsQueryResponse {
+                results: vec![SupportResult::Support],
+            }),
+        );
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("operatorOf".to_string()),
+            returning(|| OperatorOfQueryResponse(vec![true])),
+        );
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("balanceOf".to_string()),
+            returning(|| BalanceOfQueryResponse::<ContractTokenAmount>(vec![
+                token_amount(2),
+            ])),
+        );
+
+        host
+    }
+
+    /// Context for ACCOUNT_1 buying one unit of token 1 from ACCOUNT_0.
+    fn buy_context(parameter_bytes: &[u8]) -> TestReceiveContext<'_> {
+        let mut ctx = TestReceiveContext::default();
+        ctx.set_sender(ADDRESS_1);
+        ctx.set_owner(ACCOUNT_0);
+        ctx.set_self_address(MARKET_CONTRACT_ADDRESS);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(0));
+        ctx.set_parameter(parameter_bytes);
+        ctx
+    }
+
+    fn buy_params() -> TransferParams {
+        TransferParams {
+            cis_contract_address: CIS_CONTRACT_ADDRESS,
+            token_id: token_id(1),
+            to: Receiver::Account(ACCOUNT_1),
+            owner: ADDRESS_0,
+            quantity: token_amount(1),
+            referrer: None,
+            attestation: None,
+        }
+    }
+
+    #[concordium_test]
+    fn hostile_cis2_callback_finds_dex_locked() {
+        let parameter_bytes = to_bytes(&buy_params());
+        let ctx = buy_context(&parameter_bytes);
+        let mut host = host_with_listing();
+
+        // A hostile CIS2 contract which calls back into the Dex from its
+        // `transfer` entrypoint. It records what the callback would see.
+        let observed = Rc::new(Cell::new(None));
+        let observed_by_callback = observed.clone();
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("transfer".to_string()),
+            MockFn::new_v1(move |_p, _a, _b, state: &mut ContractState<TestStateApi>| {
+                let listed_quantity = state
+                    .get_token(
+                        &TokenInfo {
+                            id: token_id(1),
+                            address: CIS_CONTRACT_ADDRESS,
+                        },
+                        &ADDRESS_0,
+                    )
+                    .map(|listed_token| listed_token.quantity);
+                observed_by_callback.set(Some((state.locked, listed_quantity)));
+                Ok::<_, CallContractError<()>>((false, ()))
+            }),
+        );
+
+        let mut logger = TestLogger::init();
+        let res = transfer(&ctx, &mut host, Amount::from_ccd(1), &mut logger, &crypto());
+
+        claim!(res.is_ok(), "Results in rejection");
+        // The callback finds the Dex locked, and the listing already reduced.
+        claim_eq!(
+            observed.get(),
+            Some((true, Some(token_amount(1))))
+        );
+        claim!(!host.state().locked, "Lock is not released");
+    }
+
+    #[concordium_test]
+    fn reentrant_calls_are_rejected() {
+        let parameter_bytes = to_bytes(&buy_params());
+        let ctx = buy_context(&parameter_bytes);
+        let mut host = host_with_listing();
+
+        // The state a callback from a CIS2 contract sees in the middle of a
+        // trade.
+        host.state_mut().locked = true;
+
+        claim_eq!(
+            transfer(&ctx, &mut host, Amount::from_ccd(1), &mut TestLogger::init(), &crypto()),
+            Err(DexError::Reentrancy)
+        );
+        claim_eq!(add(&ctx, &mut host, &crypto()), Err(DexError::Reentrancy));
+    }
+
+    fn admin_context(
+        sender: Address,
+        millis: u64,
+        parameter_bytes: &[u8],
+    ) -> TestReceiveContext<'_> {
+        let mut ctx = TestReceiveContext::default();
+        ctx.set_sender(sender);
+        ctx.set_owner(ACCOUNT_0);
+        ctx.set_self_address(MARKET_CONTRACT_ADDRESS);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(millis));
+        ctx.set_parameter(parameter_bytes);
+        ctx
+    }
+
+    /// Makes a call, releasing the lock if the call is rejected. On chain a
+    /// rejected call leaves no changes to the state behind.
+    fn call_rolled_back<R>(
+        host: &mut TestHost<ContractState<TestStateApi>>,
+        call: impl FnOnce(&mut TestHost<ContractState<TestStateApi>>) -> Result<R, DexError>,
+    ) -> Result<R, DexError> {
+        let res = call(host);
+        if res.is_err() {
+            host.state_mut().locked = false;
+        }
+        res
+    }
+
+    #[concordium_test]
+    fn passed_proposal_is_applied_after_timelock() {
+        let day = Duration::from_days(1).millis();
+        let mut state_builder = TestStateBuilder::new();
+        let mut state = State::new(&mut state_builder);
+        state.governance = Some(GovernanceConfig {
+            token: GOVERNANCE_TOKEN_ADDRESS,
+            token_id: token_id(0),
+            voting_period: Duration::from_days(1),
+            timelock: Duration::from_days(1),
+            quorum: token_amount(50),
+            threshold_basis_points: 5_000,
+        });
+        let mut host = TestHost::new(state, state_builder);
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            GOVERNANCE_TOKEN_ADDRESS,
+            OwnedEntrypointName::new_unchecked("balanceOfAt".to_string()),
+            returning(|| BalanceOfQueryResponse::<ContractTokenAmount>(vec![
+                token_amount(100),
+            ])),
+        );
+
+        let fees = FeeConfig {
+            commission_basis_points: 250,
+            referral_share_basis_points: 0,
+        };
+        // Once governance is configured, the owner can no longer change
+        // parameters directly.
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                set_fees(&admin_context(ADDRESS_0, 0, &to_bytes(&fees)), host)
+            }),
+            Err(DexError::Unauthorized)
+        );
+
+        let parameter_bytes = to_bytes(&DexAction::SetFees(fees));
+        let ctx = admin_context(ADDRESS_1, 0, &parameter_bytes);
+        let proposal_id = propose(&ctx, &mut host).expect("Proposal is rejected");
+
+        let parameter_bytes = to_bytes(&VoteParams {
+            proposal_id,
+            support: true,
+        });
+        let ctx = admin_context(ADDRESS_1, 1, &parameter_bytes);
+        claim!(vote(&ctx, &mut host).is_ok(), "Vote is rejected");
+        claim_eq!(
+            call_rolled_back(&mut host, |host| vote(&ctx, host)),
+            Err(DexError::AlreadyVoted)
+        );
+
+        let parameter_bytes = to_bytes(&proposal_id);
+        let ctx = admin_context(ADDRESS_0, day, &parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| execute_proposal(&ctx, host)),
+            Err(DexError::TimelockNotElapsed)
+        );
+
+        let ctx = admin_context(ADDRESS_0, 2 * day, &parameter_bytes);
+        claim!(execute_proposal(&ctx, &mut host).is_ok(), "Execution is rejected");
+        claim_eq!(host.state().fees, fees);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| execute_proposal(&ctx, host)),
+            Err(DexError::ProposalExecuted)
+        );
+    }
+
+    #[concordium_test]
+    fn admin_action_is_queued_at_threshold() {
+        let account_2 = AccountAddress([2u8; 32]);
+        let mut state_builder = TestStateBuilder::new();
+        let mut state = State::new(&mut state_builder);
+        state.admins = Some(MultisigConfig {
+            signers: vec![ACCOUNT_0, ACCOUNT_1, account_2],
+            threshold: 2,
+        });
+        let mut host = TestHost::new(state, state_builder);
+
+        let fees = FeeConfig {
+            commission_basis_points: 100,
+            referral_share_basis_points: 0,
+        };
+        // The owner key alone can no longer change fees.
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                set_fees(&admin_context(ADDRESS_0, 0, &to_bytes(&fees)), host)
+            }),
+            Err(DexError::Unauthorized)
+        );
+
+        let parameter_bytes = to_bytes(&AdminAction::<ContractTokenId, ContractTokenAmount>::Dex(
+            DexAction::SetFees(fees),
+        ));
+        let ctx = admin_context(ADDRESS_0, 0, &parameter_bytes);
+        let proposal_id = propose_admin_action(&ctx, &mut host).expect("Proposal is rejected");
+        claim_eq!(host.state().fees.commission_basis_points, 0);
+
+        let parameter_bytes = to_bytes(&proposal_id);
+        let ctx = admin_context(ADDRESS_0, 0, &parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| approve_admin_action(&ctx, host)),
+            Err(DexError::AlreadyApproved)
+        );
+
+        let ctx = admin_context(ADDRESS_1, 0, &parameter_bytes);
+        claim!(approve_admin_action(&ctx, &mut host).is_ok(), "Approval is rejected");
+        claim!(!host.state().locked, "Lock is not released");
+        claim_eq!(host.state().queued_admin_actions().len(), 1);
+
+        // Anyone can run the action once the admin delay has passed.
+        let parameter_bytes = to_bytes(&0u64);
+        let ctx = admin_context(ADDRESS_1, Duration::from_days(2).millis() - 1, &parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| execute_queued_admin_action(&ctx, host)),
+            Err(DexError::TimelockNotElapsed)
+        );
+        let ctx = admin_context(ADDRESS_1, Duration::from_days(2).millis(), &parameter_bytes);
+        claim!(execute_queued_admin_action(&ctx, &mut host).is_ok(), "Execution is rejected");
+        claim_eq!(host.state().fees, fees);
+        claim!(host.state().queued_admin_actions().is_empty(), "Action is still queued");
+    }
+
+    #[concordium_test]
+    fn only_pausing_skips_the_admin_delay() {
+        let mut state_builder = TestStateBuilder::new();
+        let state = State::new(&mut state_builder);
+        let mut host = TestHost::new(state, state_builder);
+
+        let parameter_bytes = to_bytes(&true);
+        let ctx = admin_context(ADDRESS_0, 0, &parameter_bytes);
+        claim!(set_paused(&ctx, &mut host).is_ok(), "Pause is rejected");
+        claim!(host.state().paused, "Trading is not paused");
+
+        let parameter_bytes = to_bytes(&false);
+        let ctx = admin_context(ADDRESS_0, 0, &parameter_bytes);
+        claim!(set_paused(&ctx, &mut host).is_ok(), "Resume is rejected");
+        claim!(host.state().paused, "Resuming skipped the delay");
+
+        let parameter_bytes = to_bytes(&0u64);
+        let ctx = admin_context(ADDRESS_1, 0, &parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| cancel_admin_action(&ctx, host)),
+            Err(DexError::Unauthorized)
+        );
+        let ctx = admin_context(ADDRESS_0, 0, &parameter_bytes);
+        claim!(cancel_admin_action(&ctx, &mut host).is_ok(), "Cancellation is rejected");
+        claim!(host.state().queued_admin_actions().is_empty(), "Action is still queued");
+    }
+
+    #[concordium_test]
+    fn aliases_share_listings_and_proceeds() {
+        let alias = Address::Account(ACCOUNT_0.get_alias_unchecked(7));
+        let mut host = host_with_listing();
+        let token_info = TokenInfo {
+            id: token_id(1),
+            address: CIS_CONTRACT_ADDRESS,
+        };
+
+        let listing = host
+            .state()
+            .get_token(&token_info, &alias)
+            .expect("Listing is not found under an alias");
+        // Listing again under an alias keeps the existing listing.
+        host.state_mut().list_token(&token_info, &alias, TokenPriceState {
+            quantity: token_amount(5),
+            holder: alias,
+            ..listing
+        });
+        claim_eq!(host.state().token_prices.iter().count(), 1);
+        claim_eq!(
+            host.state().get_token(&token_info, &ADDRESS_0),
+            Some(listing)
+        );
+
+        host.state_mut()
+            .credit_proceeds(&alias, Amount::from_ccd(3));
+        claim_eq!(
+            host.state().proceeds_of(&ADDRESS_0),
+            Amount::from_ccd(3)
+        );
+    }
+
+    #[concordium_test]
+    fn overpayment_is_credited_to_the_buyer() {
+        let parameter_bytes = to_bytes(&buy_params());
+        let ctx = buy_context(&parameter_bytes);
+        let mut host = host_with_listing();
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("transfer".to_string()),
+            returning(|| ()),
+        );
+
+        let mut logger = TestLogger::init();
+        let res = transfer(&ctx, &mut host, Amount::from_ccd(3), &mut logger, &crypto());
+
+        claim!(res.is_ok(), "Overpaid purchase is rejected");
+        claim_eq!(host.state().proceeds_of(&ADDRESS_0), Amount::from_ccd(1));
+        claim_eq!(host.state().proceeds_of(&ADDRESS_1), Amount::from_ccd(2));
+        claim_eq!(host.state().total_proceeds, Amount::from_ccd(3));
+    }
+
+    #[concordium_test]
+    fn contract_recipients_need_contract_principals() {
+        let wallet = ContractAddress::new(4, 0);
+        let params = TransferParams {
+            to: Receiver::Contract(
+                wallet,
+                OwnedEntrypointName::new_unchecked("onReceivingCIS2".to_string()),
+            ),
+            ..buy_params()
+        };
+        let parameter_bytes = to_bytes(&params);
+        let ctx = buy_context(&parameter_bytes);
+        let mut host = host_with_listing();
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("transfer".to_string()),
+            returning(|| ()),
+        );
+
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                transfer(&ctx, host, Amount::from_ccd(1), &mut TestLogger::init(), &crypto())
+            }),
+            Err(DexError::CalledByAContract)
+        );
+
+        host.state_mut().allow_contract_principals = true;
+        let mut logger = TestLogger::init();
+        claim!(
+            transfer(&ctx, &mut host, Amount::from_ccd(1), &mut logger, &crypto()).is_ok(),
+            "Transfer to a contract is rejected"
+        );
+        claim_eq!(
+            host.state().proceeds_of(&ADDRESS_0),
+            Amount::from_ccd(1)
+        );
+    }
+
+    #[concordium_test]
+    fn identity_policy_needs_trusted_claims() {
+        let policy = IdentityPolicy {
+            minimum_age: 18,
+            allowed_residence: vec![],
+            blocked_residence: vec![*b"KP"],
+            allowed_nationalities: vec![],
+        };
+        let claims = IdentityClaims {
+            subject: ADDRESS_1,
+            minimum_age: 18,
+            country_of_residence: *b"DE",
+            nationality: *b"DE",
+            expires_at: Timestamp::from_timestamp_millis(1_000),
+        };
+        claim_eq!(policy.check(&claims), Ok(()));
+        claim_eq!(
+            policy.check(&IdentityClaims {
+                minimum_age: 16,
+                ..claims.clone()
+            }),
+            Err(IneligibleReason::BelowMinimumAge)
+        );
+        claim_eq!(
+            policy.check(&IdentityClaims {
+                country_of_residence: *b"KP",
+                ..claims.clone()
+            }),
+            Err(IneligibleReason::ResidenceNotAllowed)
+        );
+
+        let mut host = host_with_listing();
+        host.state_mut().identity_policy = Some(policy);
+        let parameter_bytes = to_bytes(&buy_params());
+        let ctx = buy_context(&parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                transfer(&ctx, host, Amount::from_ccd(1), &mut TestLogger::init(), &crypto())
+            }),
+            Err(DexError::AttestationRequired)
+        );
+
+        let params = TransferParams {
+            attestation: Some(SignedAttestation {
+                verifier: ACCOUNT_1,
+                claims,
+                signatures: AccountSignatures {
+                    sigs: std::collections::BTreeMap::new(),
+                },
+            }),
+            ..buy_params()
+        };
+        let parameter_bytes = to_bytes(&params);
+        let ctx = buy_context(&parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                transfer(&ctx, host, Amount::from_ccd(1), &mut TestLogger::init(), &crypto())
+            }),
+            Err(DexError::UnknownVerifier)
+        );
+    }
+
+    #[concordium_test]
+    fn token_policy_applies_to_its_token_only() {
+        let mut host = host_with_listing();
+        host.state_mut()
+            .collections
+            .get_mut(&CIS_CONTRACT_ADDRESS)
+            .expect("Collection is not registered")
+            .owner = Some(ACCOUNT_1);
+        let policy = IdentityPolicy {
+            minimum_age: 18,
+            allowed_residence: vec![],
+            blocked_residence: vec![],
+            allowed_nationalities: vec![],
+        };
+        let parameter_bytes = to_bytes(&SetTokenPolicyParams {
+            cis_contract_address: CIS_CONTRACT_ADDRESS,
+            token_id: token_id(1),
+            policy: Some(policy.clone()),
+        });
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                set_token_policy(&admin_context(ADDRESS_0, 0, &parameter_bytes), host)
+            }),
+            Err(DexError::Unauthorized)
+        );
+        let ctx = admin_context(ADDRESS_1, 0, &parameter_bytes);
+        claim!(set_token_policy(&ctx, &mut host).is_ok(), "Setting the policy is rejected");
+
+        let market = |id| TokenInfo {
+            id: token_id(id),
+            address: CIS_CONTRACT_ADDRESS,
+        };
+        claim_eq!(host.state().identity_policies_of(&market(1)), vec![policy]);
+        claim_eq!(host.state().identity_policies_of(&market(2)), vec![]);
+        let parameter_bytes = to_bytes(&buy_params());
+        let ctx = buy_context(&parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                transfer(&ctx, host, Amount::from_ccd(1), &mut TestLogger::init(), &crypto())
+            }),
+            Err(DexError::AttestationRequired)
+        );
+    }
+
+    #[concordium_test]
+    fn auctions_need_identity() {
+        let mut host = host_with_auction();
+        host.state_mut().identity_policy = Some(IdentityPolicy {
+            minimum_age: 18,
+            allowed_residence: vec![],
+            blocked_residence: vec![],
+            allowed_nationalities: vec![],
+        });
+
+        let parameter_bytes = to_bytes(&CreateAuctionParams {
+            cis_contract_address: CIS_CONTRACT_ADDRESS,
+            token_id: token_id(1),
+            quantity: token_amount(1),
+            reserve_price: Amount::from_ccd(1),
+            min_increment: Amount::from_ccd(1),
+            start: Timestamp::from_timestamp_millis(0),
+            end: Timestamp::from_timestamp_millis(1_000),
+            return_to: Receiver::Account(ACCOUNT_1),
+            attestation: None,
+        });
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                create_auction(&admin_context(ADDRESS_1, 0, &parameter_bytes), host, &crypto())
+            }),
+            Err(DexError::AttestationRequired)
+        );
+        let parameter_bytes = to_bytes(&BidParams {
+            auction_id: 0,
+            to: Receiver::Account(ACCOUNT_1),
+            attestation: None,
+        });
+        let ctx = admin_context(ADDRESS_1, 0, &parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| bid(&ctx, host, Amount::from_ccd(10), &crypto())),
+            Err(DexError::AttestationRequired)
+        );
+
+        host.state_mut()
+            .apply_action(DexAction::SetVerifier(ACCOUNT_0, true));
+        let claims_bytes = to_bytes(&IdentityClaims {
+            subject: ADDRESS_1,
+            minimum_age: 18,
+            country_of_residence: *b"DE",
+            nationality: *b"DE",
+            expires_at: Timestamp::from_timestamp_millis(1_000),
+        });
+        claim!(
+            post_attestation(&admin_context(ADDRESS_0, 0, &claims_bytes), &mut host).is_ok(),
+            "Posting the attestation is rejected"
+        );
+        claim!(
+            bid(&ctx, &mut host, Amount::from_ccd(10), &crypto()).is_ok(),
+            "Bid of an attested bidder is rejected"
+        );
+    }
+
+    #[concordium_test]
+    fn registry_claims_expire_and_can_be_revoked() {
+        let mut host = host_with_listing();
+        host.state_mut().identity_policy = Some(IdentityPolicy {
+            minimum_age: 18,
+            allowed_residence: vec![],
+            blocked_residence: vec![],
+            allowed_nationalities: vec![],
+        });
+        host.state_mut()
+            .apply_action(DexAction::SetVerifier(ACCOUNT_0, true));
+        let market = TokenInfo {
+            id: token_id(1),
+            address: CIS_CONTRACT_ADDRESS,
+        };
+        let eligibility = |host: &TestHost<ContractState<TestStateApi>>, millis| {
+            host.state().check_eligibility(
+                &market,
+                &ADDRESS_1,
+                Timestamp::from_timestamp_millis(millis),
+            )
+        };
+        claim_eq!(eligibility(&host, 0), Err(IneligibleReason::NotAttested));
+
+        let claims = IdentityClaims {
+            subject: ADDRESS_1,
+            minimum_age: 18,
+            country_of_residence: *b"DE",
+            nationality: *b"DE",
+            expires_at: Timestamp::from_timestamp_millis(1_000),
+        };
+        let parameter_bytes = to_bytes(&claims);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                post_attestation(&admin_context(ADDRESS_1, 0, &parameter_bytes), host)
+            }),
+            Err(DexError::UnknownVerifier)
+        );
+        claim!(
+            post_attestation(&admin_context(ADDRESS_0, 0, &parameter_bytes), &mut host).is_ok(),
+            "Verifier cannot post claims"
+        );
+        claim_eq!(eligibility(&host, 0), Ok(()));
+        claim_eq!(eligibility(&host, 1_000), Err(IneligibleReason::Expired));
+
+        let parameter_bytes = to_bytes(&ADDRESS_1);
+        claim!(
+            revoke_attestation(&admin_context(ADDRESS_0, 0, &parameter_bytes), &mut host).is_ok(),
+            "Verifier cannot revoke claims"
+        );
+        claim_eq!(eligibility(&host, 0), Err(IneligibleReason::Revoked));
+    }
+
+    #[concordium_test]
+    fn blocked_addresses_cannot_trade() {
+        let compliance = AccountAddress([9u8; 32]);
+        let mut host = host_with_listing();
+        host.state_mut().compliance = Some(compliance);
+        let alias = Address::Account(ACCOUNT_1.get_alias_unchecked(3));
+        let parameter_bytes = to_bytes(&alias);
+        let mut logger = TestLogger::init();
+
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                block(&admin_context(ADDRESS_0, 0, &parameter_bytes), host, &mut logger)
+            }),
+            Err(DexError::Unauthorized)
+        );
+        let ctx = admin_context(Address::Account(compliance), 0, &parameter_bytes);
+        claim!(block(&ctx, &mut host, &mut logger).is_ok(), "Blocking is rejected");
+        claim_eq!(
+            logger.logs,
+            vec![to_bytes(&DexEvent::Blocked(BlocklistEvent { address: alias }))]
+        );
+
+        let parameter_bytes = to_bytes(&vec![ADDRESS_0, ADDRESS_1]);
+        claim_eq!(
+            is_blocked(&admin_context(ADDRESS_0, 0, &parameter_bytes), &host),
+            Ok(true)
+        );
+        let parameter_bytes = to_bytes(&buy_params());
+        let ctx = buy_context(&parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                transfer(&ctx, host, Amount::from_ccd(1), &mut TestLogger::init(), &crypto())
+            }),
+            Err(DexError::Blocked)
+        );
+
+        let parameter_bytes = to_bytes(&ADDRESS_1);
+        let ctx = admin_context(Address::Account(compliance), 0, &parameter_bytes);
+        claim!(unblock(&ctx, &mut host, &mut logger).is_ok(), "Unblocking is rejected");
+        claim!(!host.state().is_blocked(&alias), "Address is still blocked");
+    }
+
+    #[concordium_test]
+    fn blocked_sellers_cannot_withdraw() {
+        let parameter_bytes = to_bytes(&buy_params());
+        let ctx = buy_context(&parameter_bytes);
+        let mut host = host_with_listing();
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("transfer".to_string()),
+            returning(|| ()),
+        );
+        let mut logger = TestLogger::init();
+        claim!(
+            transfer(&ctx, &mut host, Amount::from_ccd(1), &mut logger, &crypto()).is_ok(),
+            "Purchase is rejected"
+        );
+
+        // The seller is blocked after the sale, and their proceeds stay put.
+        let _ = host.state_mut().blocked.insert(canonical_address(&ADDRESS_0));
+        host.set_self_balance(Amount::from_ccd(1));
+        let ctx = admin_context(ADDRESS_0, 0, &[]);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| withdraw(&ctx, host)),
+            Err(DexError::Blocked)
+        );
+        claim_eq!(host.get_transfers(), []);
+        claim_eq!(host.state().proceeds_of(&ADDRESS_0), Amount::from_ccd(1));
+    }
+
+    #[concordium_test]
+    fn shares_of_blocked_recipients_go_to_the_marketplace() {
+        let mut host = host_with_listing();
+        let referrer = AccountAddress([2u8; 32]);
+        let marketplace = Address::Account(AccountAddress([3u8; 32]));
+        host.state_mut().fees = FeeConfig {
+            commission_basis_points: 500,
+            referral_share_basis_points: 2_000,
+        };
+        let _ = host.state_mut().royalties.insert(CIS_CONTRACT_ADDRESS, Royalty {
+            basis_points: 250,
+            recipient: ACCOUNT_1,
+        });
+        let _ = host.state_mut().blocked.insert(canonical_address(&ADDRESS_1));
+        let _ = host.state_mut().blocked.insert(canonical_address(&Address::Account(referrer)));
+
+        distribute_amounts(
+            &mut host,
+            Amount::from_ccd(200),
+            &CIS_CONTRACT_ADDRESS,
+            &ADDRESS_0,
+            &marketplace,
+            Some(referrer),
+            Timestamp::from_timestamp_millis(0),
+        );
+
+        let state = host.state();
+        claim_eq!(state.proceeds_of(&ADDRESS_0), Amount::from_ccd(185));
+        claim_eq!(state.proceeds_of(&marketplace), Amount::from_ccd(15));
+        claim_eq!(state.proceeds_of(&ADDRESS_1), Amount::zero());
+        claim_eq!(state.proceeds_of(&Address::Account(referrer)), Amount::zero());
+        claim_eq!(state.referral_stats_of(&referrer).trades, 0);
+    }
+
+    /// Host with auction 0 of 1 unit of token 1 by ACCOUNT_0, open from time 0
+    /// to 1_000_000 with a reserve price of 10 CCD and a minimum increment of
+    /// 1 CCD.
+    fn host_with_auction() -> TestHost<ContractState<TestStateApi>> {
+        let mut host = host_with_listing();
+        host.state_mut().create_auction(AuctionState {
+            seller: ADDRESS_0,
+            return_to: Receiver::Account(ACCOUNT_0),
+            contract: CIS_CONTRACT_ADDRESS,
+            token_id: token_id(1),
+            quantity: token_amount(1),
+            reserve_price: Amount::from_ccd(10),
+            min_increment: Amount::from_ccd(1),
+            start: Timestamp::from_timestamp_millis(0),
+            end: Timestamp::from_timestamp_millis(1_000_000),
+            highest_bid: None,
+        });
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("transfer".to_string()),
+            returning(|| ()),
+        );
+        host
+    }
+
+    #[concordium_test]
+    fn blocked_addresses_cannot_use_auctions() {
+        let mut host = host_with_auction();
+        let _ = host.state_mut().blocked.insert(canonical_address(&ADDRESS_1));
+
+        let parameter_bytes = to_bytes(&CreateAuctionParams {
+            cis_contract_address: CIS_CONTRACT_ADDRESS,
+            token_id: token_id(1),
+            quantity: token_amount(1),
+            reserve_price: Amount::from_ccd(1),
+            min_increment: Amount::from_ccd(1),
+            start: Timestamp::from_timestamp_millis(0),
+            end: Timestamp::from_timestamp_millis(1_000),
+            return_to: Receiver::Account(ACCOUNT_1),
+            attestation: None,
+        });
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                create_auction(&admin_context(ADDRESS_1, 0, &parameter_bytes), host, &crypto())
+            }),
+            Err(DexError::Blocked)
+        );
+        let bid_bytes = to_bytes(&BidParams {
+            auction_id: 0,
+            to: Receiver::Account(ACCOUNT_1),
+            attestation: None,
+        });
+        let ctx = admin_context(ADDRESS_1, 0, &bid_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| bid(&ctx, host, Amount::from_ccd(10), &crypto())),
+            Err(DexError::Blocked)
+        );
 
-    let res: Cis2ClientResult<A> = cis2_client.balance_of(host, token_id, Address::Account(owner));
-    let res = match res {
-        Ok(res) => res,
-        Err(_) => bail!(DexError::Cis2ClientError),
-    };
-    ensure!(
-        res.cmp(&minimum_balance).is_ge(),
-        DexError::NoBalance
-    );
+        // A winner blocked after bidding is refunded, and the token goes back
+        // to the seller.
+        host.state_mut().blocked.remove(&canonical_address(&ADDRESS_1));
+        claim!(
+            bid(&ctx, &mut host, Amount::from_ccd(10), &crypto()).is_ok(),
+            "Bid is rejected"
+        );
+        let _ = host.state_mut().blocked.insert(canonical_address(&ADDRESS_1));
+        host.set_self_balance(Amount::from_ccd(10));
+        let parameter_bytes = to_bytes(&0u64);
+        let ctx = admin_context(ADDRESS_0, 1_000_000, &parameter_bytes);
+        claim!(
+            settle_auction(&ctx, &mut host, &mut TestLogger::init()).is_ok(),
+            "Settling is rejected"
+        );
+        claim_eq!(host.get_transfers(), [(ACCOUNT_1, Amount::from_ccd(10))]);
+        claim_eq!(host.state().proceeds_of(&ADDRESS_0), Amount::zero());
 
-    Ok(())
-}
+        // So is it if the seller is blocked after the bid.
+        let mut host = host_with_auction();
+        host.set_self_balance(Amount::from_ccd(10));
+        let receiver = record_receiver(&mut host);
+        claim_eq!(place_bid(&mut host, ADDRESS_1, 0, Amount::from_ccd(10)), Ok(()));
+        let _ = host.state_mut().blocked.insert(canonical_address(&ADDRESS_0));
+        claim!(
+            settle_auction(&ctx, &mut host, &mut TestLogger::init()).is_ok(),
+            "Settling is rejected"
+        );
+        claim_eq!(receiver.get(), Some(ADDRESS_0));
+        claim_eq!(host.get_transfers(), [(ACCOUNT_1, Amount::from_ccd(10))]);
+        claim_eq!(host.state().proceeds_of(&ADDRESS_0), Amount::zero());
+    }
 
+    /// Bids `amount` on the auction of `host_with_auction` from `sender`, at
+    /// time `millis`.
+    fn place_bid(
+        host: &mut TestHost<ContractState<TestStateApi>>,
+        sender: Address,
+        millis: u64,
+        amount: Amount,
+    ) -> Result<(), DexError> {
+        let to = match sender {
+            Address::Account(account) => Receiver::Account(account),
+            Address::Contract(contract) => Receiver::Contract(
+                contract,
+                OwnedEntrypointName::new_unchecked("onReceivingCIS2".to_string()),
+            ),
+        };
+        let parameter_bytes = to_bytes(&BidParams {
+            auction_id: 0,
+            to,
+            attestation: None,
+        });
+        let ctx = admin_context(sender, millis, &parameter_bytes);
+        call_rolled_back(host, |host| bid(&ctx, host, amount, &crypto()))
+    }
 
-struct DistributableAmounts {
-    to_primary_owner: Amount,
+    /// Mocks the `transfer` entrypoint of the CIS2 contract, recording the
+    /// receiver of the last transfer.
+    fn record_receiver(
+        host: &mut TestHost<ContractState<TestStateApi>>,
+    ) -> Rc<Cell<Option<Address>>> {
+        let receiver = Rc::new(Cell::new(None));
+        let recorded = receiver.clone();
+        TestHost::setup_mock_entrypoint(
+            host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("transfer".to_string()),
+            MockFn::new_v1(move |parameter, _amount, _balance, _state| {
+                let transfers: concordium_cis2::TransferParams<
+                    ContractTokenId,
+                    ContractTokenAmount,
+                > = from_bytes(parameter.as_ref()).map_err(|_| CallContractError::Trap)?;
+                recorded.set(transfers.0.first().map(|transfer| transfer.to.address()));
+                Ok((false, ()))
+            }),
+        );
+        receiver
     }
 
-// Distribute the funds to appropriate addresses if needed.
-fn distribute_amounts<S: HasStateApi, T: IsTokenId + Copy, A: IsTokenAmount + Copy, E>(
-    host: &mut impl HasHost<State<S, T, A>, StateApiType = S, ReturnValueType = E>,
-    ctx: &ReceiveContext,
-    amount: Amount,
-    token_owner: &AccountAddress,
-    marketplace_owner: &AccountAddress,
-) -> Result<(), DexError> {
-    let amounts = calculate_amounts(
-        &amount,
-    );
+    #[concordium_test]
+    fn bids_respect_the_reserve_and_the_increment_and_refund_the_outbid() {
+        let mut host = host_with_auction();
+        host.set_self_balance(Amount::from_ccd(100));
+        let address_2 = Address::Account(AccountAddress([2u8; 32]));
 
-    host.invoke_transfer(marketplace_owner, amount)
-        .map_err(|_| DexError::InvokeTransferError)?;
+        claim_eq!(
+            place_bid(&mut host, ADDRESS_1, 0, Amount::from_ccd(9)),
+            Err(DexError::BidTooLow)
+        );
+        claim_eq!(place_bid(&mut host, ADDRESS_1, 0, Amount::from_ccd(10)), Ok(()));
+        claim_eq!(host.get_transfers(), []);
 
-    if amounts
-        .to_primary_owner
-        .cmp(&Amount::from_micro_ccd(0))
-        .is_gt()
-    {
-        host.invoke_transfer(marketplace_owner, amounts.to_primary_owner)
-            .map_err(|_| DexError::InvokeTransferError)?;
+        claim_eq!(
+            place_bid(&mut host, address_2, 0, Amount::from_micro_ccd(10_999_999)),
+            Err(DexError::BidTooLow)
+        );
+        claim_eq!(place_bid(&mut host, address_2, 0, Amount::from_ccd(11)), Ok(()));
+        claim_eq!(host.get_transfers(), [(ACCOUNT_1, Amount::from_ccd(10))]);
+        let highest_bid = host.state().get_auction(0).and_then(|auction| auction.highest_bid);
+        claim_eq!(
+            highest_bid.map(|bid| (bid.bidder, bid.amount)),
+            Some((address_2, Amount::from_ccd(11)))
+        );
     }
 
-    if amounts
-        .to_primary_owner
-        .cmp(&Amount::from_micro_ccd(0))
-        .is_gt()
-    {
-         };
+    #[concordium_test]
+    fn contracts_can_auction_and_bid() {
+        let wallet = ContractAddress::new(4, 0);
+        let on_receiving = OwnedEntrypointName::new_unchecked("onReceivingCIS2".to_string());
+        let mut host = host_with_auction();
+        host.set_self_balance(Amount::from_ccd(100));
+        let receiver = record_receiver(&mut host);
 
-    Ok(())
-    
-}
+        claim_eq!(
+            place_bid(&mut host, Address::Contract(wallet), 0, Amount::from_ccd(10)),
+            Err(DexError::CalledByAContract)
+        );
+        host.state_mut().allow_contract_principals = true;
 
-/// Calculates the amounts (Commission, Royalty & Selling Price) to be
-/// distributed
-fn calculate_amounts(
-    amount: &Amount,
-) -> DistributableAmounts {
+        // An outbid contract is credited its bid, and an outbid account is
+        // paid at once.
+        claim_eq!(place_bid(&mut host, Address::Contract(wallet), 0, Amount::from_ccd(10)), Ok(()));
+        claim_eq!(place_bid(&mut host, ADDRESS_1, 0, Amount::from_ccd(11)), Ok(()));
+        claim_eq!(host.get_transfers(), []);
+        claim_eq!(
+            host.state().proceeds_of(&Address::Contract(wallet)),
+            Amount::from_ccd(10)
+        );
+        claim_eq!(place_bid(&mut host, Address::Contract(wallet), 0, Amount::from_ccd(12)), Ok(()));
+        claim_eq!(host.get_transfers(), [(ACCOUNT_1, Amount::from_ccd(11))]);
 
-    DistributableAmounts {
-        to_primary_owner: amount.to_owned()
-        }
-        
-          // distribute_amounts(
-    //     host,
-    //     amount,
-    //     &params.owner,
-    //     &ctx.owner(),
-    // )?;
+        let parameter_bytes = to_bytes(&0u64);
+        let ctx = admin_context(ADDRESS_1, 1_000_000, &parameter_bytes);
+        claim!(
+            settle_auction(&ctx, &mut host, &mut TestLogger::init()).is_ok(),
+            "Settling is rejected"
+        );
+        claim_eq!(receiver.get(), Some(Address::Contract(wallet)));
+        claim_eq!(host.state().proceeds_of(&ADDRESS_0), Amount::from_ccd(12));
 
-    // host.state_mut().decrease_listed_quantity(
-    //     &TokenOwnerInfo::from(token_info, &params.owner),
-    //     params.quantity,
-    // );
+        // A contract seller gets unsold tokens back on its entrypoint, and
+        // cannot have them returned anywhere else.
+        let params = |return_to| CreateAuctionParams {
+            cis_contract_address: CIS_CONTRACT_ADDRESS,
+            token_id: token_id(1),
+            quantity: token_amount(1),
+            reserve_price: Amount::from_ccd(1),
+            min_increment: Amount::from_ccd(1),
+            start: Timestamp::from_timestamp_millis(0),
+            end: Timestamp::from_timestamp_millis(1_000),
+            return_to,
+            attestation: None,
+        };
+        let parameter_bytes = to_bytes(&params(Receiver::Account(ACCOUNT_1)));
+        let ctx = admin_context(Address::Contract(wallet), 0, &parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| create_auction(&ctx, host, &crypto())),
+            Err(DexError::InvalidAuctionParams)
+        );
+        let parameter_bytes = to_bytes(&params(Receiver::Contract(wallet, on_receiving)));
+        let ctx = admin_context(Address::Contract(wallet), 0, &parameter_bytes);
+        claim_eq!(create_auction(&ctx, &mut host, &crypto()), Ok(1));
 
-}
+        let parameter_bytes = to_bytes(&1u64);
+        let ctx = admin_context(ADDRESS_1, 1_000, &parameter_bytes);
+        claim!(
+            settle_auction(&ctx, &mut host, &mut TestLogger::init()).is_ok(),
+            "Settling is rejected"
+        );
+        claim_eq!(receiver.get(), Some(Address::Contract(wallet)));
+    }
 
-#[concordium_cfg_test]
-mod test {
-    use crate::{
-        add, calculate_amounts, list,
-        parameter::AddParams,
-        state::{ State, TokenInfo, TokenListItem, TokenPriceState,},
-        ContractState, ContractTokenAmount, ContractTokenId,
-    };
-    use concordium_cis2::*;
+    #[concordium_test]
+    fn late_bids_extend_the_auction() {
+        let mut host = host_with_auction();
+        host.set_self_balance(Amount::from_ccd(100));
+        let end_of = |host: &TestHost<ContractState<TestStateApi>>| {
+            host.state().get_auction(0).map(|auction| auction.end.timestamp_millis())
+        };
 
-    use concordium_std::{test_infrastructure::*, *};
+        // A bid long before the end leaves it, a bid within the extension of
+        // the end pushes it to the extension after the bid.
+        claim_eq!(place_bid(&mut host, ADDRESS_1, 0, Amount::from_ccd(10)), Ok(()));
+        claim_eq!(end_of(&host), Some(1_000_000));
+        claim_eq!(place_bid(&mut host, ADDRESS_0, 900_000, Amount::from_ccd(11)), Ok(()));
+        claim_eq!(end_of(&host), Some(1_500_000));
 
-    const ACCOUNT_0: AccountAddress = AccountAddress([0u8; 32]);
-    const ADDRESS_0: Address = Address::Account(ACCOUNT_0);
-    const CIS_CONTRACT_ADDRESS: ContractAddress = ContractAddress {
-        index: 1,
-        subindex: 0,
-    };
-    const MARKET_CONTRACT_ADDRESS: ContractAddress = ContractAddress {
-        index: 2,
-        subindex: 0,
-    };
+        claim_eq!(
+            place_bid(&mut host, ADDRESS_1, 1_500_000, Amount::from_ccd(12)),
+            Err(DexError::AuctionNotActive)
+        );
+    }
 
     #[concordium_test]
-    fn should_add_token() {
-        let token_id_1 = ContractTokenId::from(1);
-        let token_quantity_1 = ContractTokenAmount::from(1);
-        let price = Amount::from_ccd(1);
+    fn settling_delivers_to_the_winner_or_returns_to_the_seller() {
+        let parameter_bytes = to_bytes(&0u64);
 
-        let mut ctx = TestReceiveContext::default();
-        ctx.set_sender(ADDRESS_0);
-        ctx.set_self_address(MARKET_CONTRACT_ADDRESS);
+        // Without bids the token goes back to the seller.
+        let mut host = host_with_auction();
+        let receiver = record_receiver(&mut host);
+        let ctx = admin_context(ADDRESS_1, 999_999, &parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| settle_auction(&ctx, host, &mut TestLogger::init())),
+            Err(DexError::AuctionNotEnded)
+        );
+        let ctx = admin_context(ADDRESS_1, 1_000_000, &parameter_bytes);
+        claim!(
+            settle_auction(&ctx, &mut host, &mut TestLogger::init()).is_ok(),
+            "Settling is rejected"
+        );
+        claim_eq!(receiver.get(), Some(ADDRESS_0));
+        claim_eq!(host.state().proceeds_of(&ADDRESS_0), Amount::zero());
+        claim!(host.state().get_auction(0).is_none(), "Settled auction is kept");
 
-        let add_params = AddParams {
-            cis_contract_address: CIS_CONTRACT_ADDRESS,
-            price,
-            token_id: token_id_1,
-            quantity: token_quantity_1,
-        };
-        let parameter_bytes = to_bytes(&add_params);
-        ctx.set_parameter(&parameter_bytes);
+        // With a bid the token goes to the highest bidder, and the bid to the
+        // seller.
+        let mut host = host_with_auction();
+        let receiver = record_receiver(&mut host);
+        claim_eq!(place_bid(&mut host, ADDRESS_1, 0, Amount::from_ccd(10)), Ok(()));
+        claim!(
+            settle_auction(&ctx, &mut host, &mut TestLogger::init()).is_ok(),
+            "Settling is rejected"
+        );
+        claim_eq!(receiver.get(), Some(ADDRESS_1));
+        claim_eq!(host.state().proceeds_of(&ADDRESS_0), Amount::from_ccd(10));
+        claim_eq!(
+            call_rolled_back(&mut host, |host| settle_auction(&ctx, host, &mut TestLogger::init())),
+            Err(DexError::AuctionNotFound)
+        );
+    }
 
-        let mut state_builder = TestStateBuilder::new();
-        let state = State::new(&mut state_builder);
-        let mut host = TestHost::new(state, state_builder);
+    /// Sells one unit of `token` listed by `seller` to the buyback reserve, at
+    /// time `millis`.
+    fn sell_to_reserve(
+        host: &mut TestHost<ContractState<TestStateApi>>,
+        token: u8,
+        seller: Address,
+        millis: u64,
+    ) -> Result<(), DexError> {
+        let parameter_bytes = to_bytes(&TransferParams {
+            token_id: token_id(token),
+            owner: seller,
+            ..buy_params()
+        });
+        let mut ctx = buy_context(&parameter_bytes);
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(millis));
+        call_rolled_back(host, |host| {
+            transfer_cis2(&ctx, host, &mut TestLogger::init(), &crypto())
+        })
+    }
 
-        fn mock_supports(
-            _p: Parameter,
-            _a: Amount,
-            _a2: &mut Amount,
-            _s: &mut ContractState<TestStateApi>,
-        ) -> Result<(bool, SupportsQueryResponse), CallContractError<SupportsQueryResponse>>
-        {
-            Ok((
-                false,
-                SupportsQueryResponse {
-                    results: vec![SupportResult::Support],
+    /// Host with listings at 1 CCD of token 1 and 2 by ACCOUNT_0 and of token 3
+    /// by ACCOUNT_1, a buyback reserve of 100 CCD and the given limits.
+    fn host_with_buyback(limits: BuybackLimits) -> TestHost<ContractState<TestStateApi>> {
+        let mut host = host_with_listing();
+        for (token, seller) in [(2, ADDRESS_0), (3, ADDRESS_1)] {
+            host.state_mut().list_token(
+                &TokenInfo {
+                    id: token_id(token),
+                    address: CIS_CONTRACT_ADDRESS,
                 },
-            ))
+                &seller,
+                TokenPriceState {
+                    price: Amount::from_ccd(1),
+                    currency: Currency::Ccd,
+                    mode: PriceMode::Fixed,
+                    quantity: token_amount(2),
+                    listed_at: Timestamp::from_timestamp_millis(0),
+                    expires_at: None,
+                    holder: seller,
+                },
+            );
         }
+        host.state_mut().reserve = Amount::from_ccd(100);
+        host.state_mut().buyback_limits = limits;
+        TestHost::setup_mock_entrypoint(
+            &mut host,
+            CIS_CONTRACT_ADDRESS,
+            OwnedEntrypointName::new_unchecked("transfer".to_string()),
+            returning(|| ()),
+        );
+        host
+    }
 
-        fn mock_is_operator_of(
-            _p: Parameter,
-            _a: Amount,
-            _a2: &mut Amount,
-            _s: &mut ContractState<TestStateApi>,
-        ) -> Result<(bool, OperatorOfQueryResponse), CallContractError<OperatorOfQueryResponse>>
-        {
-            Ok((false, OperatorOfQueryResponse { 0: vec![true] }))
-        }
+    #[concordium_test]
+    fn buybacks_stop_at_each_daily_cap() {
+        let day = Duration::from_days(1).millis();
+        let (one, ten) = (Amount::from_ccd(1), Amount::from_ccd(10));
 
-        fn mock_balance_of(
-            _p: Parameter,
-            _a: Amount,
-            _a2: &mut Amount,
-            _s: &mut ContractState<TestStateApi>,
-        ) -> Result<
-            (bool, BalanceOfQueryResponse<ContractTokenAmount>),
-            CallContractError<BalanceOfQueryResponse<ContractTokenAmount>>,
-        > {
-            Ok((false, BalanceOfQueryResponse(vec![1.into()])))
+        // Per token: the second unit of a token waits for the next day, while
+        // another token can still be sold.
+        let mut host = host_with_buyback(BuybackLimits {
+            per_token: one,
+            per_account: ten,
+            per_day: ten,
+        });
+        claim_eq!(sell_to_reserve(&mut host, 1, ADDRESS_0, 0), Ok(()));
+        claim_eq!(
+            sell_to_reserve(&mut host, 1, ADDRESS_0, day - 1),
+            Err(DexError::BuybackLimitExceeded)
+        );
+        claim_eq!(sell_to_reserve(&mut host, 2, ADDRESS_0, day - 1), Ok(()));
+        claim_eq!(sell_to_reserve(&mut host, 1, ADDRESS_0, day), Ok(()));
+        claim_eq!(host.state().reserve, Amount::from_ccd(97));
+
+        // Per account: another token of the same seller is refused, a token of
+        // another seller is not.
+        let mut host = host_with_buyback(BuybackLimits {
+            per_token: ten,
+            per_account: one,
+            per_day: ten,
+        });
+        claim_eq!(sell_to_reserve(&mut host, 1, ADDRESS_0, 0), Ok(()));
+        claim_eq!(
+            sell_to_reserve(&mut host, 2, ADDRESS_0, 0),
+            Err(DexError::BuybackLimitExceeded)
+        );
+        claim_eq!(sell_to_reserve(&mut host, 3, ADDRESS_1, 0), Ok(()));
+        claim_eq!(sell_to_reserve(&mut host, 2, ADDRESS_0, day), Ok(()));
+
+        // Per day: any further sale is refused until the next day.
+        let mut host = host_with_buyback(BuybackLimits {
+            per_token: ten,
+            per_account: ten,
+            per_day: one,
+        });
+        claim_eq!(sell_to_reserve(&mut host, 1, ADDRESS_0, 0), Ok(()));
+        claim_eq!(
+            sell_to_reserve(&mut host, 3, ADDRESS_1, 0),
+            Err(DexError::BuybackLimitExceeded)
+        );
+        claim_eq!(sell_to_reserve(&mut host, 3, ADDRESS_1, day), Ok(()));
+        let capacity = host.state().buyback_capacity(
+            &TokenInfo {
+                id: token_id(1),
+                address: CIS_CONTRACT_ADDRESS,
+            },
+            &ADDRESS_0,
+            Timestamp::from_timestamp_millis(day),
+        );
+        claim_eq!(capacity.per_day, Amount::zero());
+        claim_eq!(capacity.per_token, ten);
+    }
+
+    #[concordium_test]
+    fn stale_listings_are_delisted() {
+        let parameter_bytes = to_bytes(&ListingParams {
+            cis_contract_address: CIS_CONTRACT_ADDRESS,
+            token_id: token_id(1),
+            owner: ADDRESS_0,
+        });
+        let ctx = admin_context(ADDRESS_1, 0, &parameter_bytes);
+        let buy_bytes = to_bytes(&buy_params());
+        let listing = TokenInfo {
+            id: token_id(1),
+            address: CIS_CONTRACT_ADDRESS,
+        };
+
+        // A backed listing is kept.
+        let mut host = host_with_listing();
+        let mut logger = TestLogger::init();
+        claim_eq!(delist_stale(&ctx, &mut host, &mut logger), Ok(false));
+        claim!(logger.logs.is_empty(), "Backed listing logs an event");
+        claim!(host.state().get_token(&listing, &ADDRESS_0).is_some(), "Backed listing removed");
+
+        let stale_hosts = [
+            ("balanceOf", DelistReason::SellerBalanceGone, DexError::SellerBalanceGone),
+            ("operatorOf", DelistReason::OperatorRevoked, DexError::OperatorRevoked),
+        ];
+        for (entrypoint, reason, error) in stale_hosts {
+            let mut host = host_with_listing();
+            let entrypoint = OwnedEntrypointName::new_unchecked(entrypoint.to_string());
+            if reason == DelistReason::SellerBalanceGone {
+                // The seller no longer holds any of the listed units.
+                TestHost::setup_mock_entrypoint(
+                    &mut host,
+                    CIS_CONTRACT_ADDRESS,
+                    entrypoint,
+                    returning(|| BalanceOfQueryResponse::<ContractTokenAmount>(vec![
+                        token_amount(0),
+                    ])),
+                );
+            } else {
+                TestHost::setup_mock_entrypoint(
+                    &mut host,
+                    CIS_CONTRACT_ADDRESS,
+                    entrypoint,
+                    returning(|| OperatorOfQueryResponse(vec![false])),
+                );
+            }
+
+            // Buying fails without touching the listing, which anyone can
+            // then delist.
+            claim_eq!(
+                call_rolled_back(&mut host, |host| {
+                    let ctx = buy_context(&buy_bytes);
+                    transfer(&ctx, host, Amount::from_ccd(1), &mut TestLogger::init(), &crypto())
+                }),
+                Err(error)
+            );
+            let mut logger = TestLogger::init();
+            claim_eq!(delist_stale(&ctx, &mut host, &mut logger), Ok(true));
+            claim_eq!(
+                logger.logs,
+                vec![to_bytes(&DexEvent::ListingRemoved(ListingRemovedEvent {
+                    token_id: token_id(1),
+                    contract: CIS_CONTRACT_ADDRESS,
+                    owner: ADDRESS_0,
+                    reason,
+                }))]
+            );
+            claim!(host.state().get_token(&listing, &ADDRESS_0).is_none(), "Listing is kept");
+            claim!(!host.state().locked, "Lock is not released");
+            claim_eq!(
+                delist_stale(&ctx, &mut host, &mut logger),
+                Err(DexError::TokenNotListed)
+            );
         }
+    }
 
+    #[concordium_test]
+    fn price_jump_halts_the_market() {
+        let mut host = host_with_listing();
         TestHost::setup_mock_entrypoint(
             &mut host,
             CIS_CONTRACT_ADDRESS,
-            OwnedEntrypointName::new_unchecked("supports".to_string()),
-            MockFn::new_v1(mock_supports),
+            OwnedEntrypointName::new_unchecked("transfer".to_string()),
+            returning(|| ()),
+        );
+        let market = TokenInfo {
+            id: token_id(1),
+            address: CIS_CONTRACT_ADDRESS,
+        };
+        host.state_mut().circuit_breaker = Some(CircuitBreakerConfig {
+            max_move_basis_points: 2_000,
+            window: Duration::from_seconds(60),
+            cooldown: Duration::from_seconds(600),
+        });
+        // An earlier trade at 2 CCD, which the listing price of 1 CCD is 50%
+        // below.
+        let start = Timestamp::from_timestamp_millis(0);
+        host.state_mut()
+            .record_trade_price(&market, Amount::from_ccd(2), start);
+
+        let parameter_bytes = to_bytes(&buy_params());
+        let ctx = buy_context(&parameter_bytes);
+        let mut logger = TestLogger::init();
+        claim!(
+            transfer(&ctx, &mut host, Amount::from_ccd(1), &mut logger, &crypto()).is_ok(),
+            "Trade which trips the breaker is rejected"
+        );
+        let halted_until = Timestamp::from_timestamp_millis(600_000);
+        claim_eq!(
+            logger.logs,
+            vec![to_bytes(&DexEvent::MarketHalted(MarketHaltedEvent {
+                token_id: token_id(1),
+                contract: CIS_CONTRACT_ADDRESS,
+                price: Amount::from_ccd(1),
+                reference_price: Amount::from_ccd(2),
+                halted_until,
+            }))]
+        );
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                transfer(&ctx, host, Amount::from_ccd(1), &mut logger, &crypto())
+            }),
+            Err(DexError::MarketHalted)
+        );
+        claim!(
+            !host.state().is_halted(&market, halted_until),
+            "Market is halted after the cooldown"
+        );
+    }
+
+    #[concordium_test]
+    fn halted_market_rejects_bids_and_settlement_is_a_trade() {
+        let mut host = host_with_auction();
+        host.set_self_balance(Amount::from_ccd(10));
+        let market = TokenInfo {
+            id: token_id(1),
+            address: CIS_CONTRACT_ADDRESS,
+        };
+        host.state_mut().circuit_breaker = Some(CircuitBreakerConfig {
+            max_move_basis_points: 2_000,
+            window: Duration::from_seconds(60),
+            cooldown: Duration::from_seconds(600),
+        });
+        let start = Timestamp::from_timestamp_millis(0);
+        host.state_mut().record_trade_price(&market, Amount::from_ccd(2), start);
+        host.state_mut().record_trade_price(&market, Amount::from_ccd(1), start);
+
+        claim_eq!(
+            place_bid(&mut host, ADDRESS_1, 0, Amount::from_ccd(10)),
+            Err(DexError::MarketHalted)
+        );
+        // The bid after the halt extends the auction to 1_200_000.
+        claim_eq!(place_bid(&mut host, ADDRESS_1, 600_000, Amount::from_ccd(10)), Ok(()));
+
+        // Settling at half the price of a trade just before trips the breaker.
+        let earlier_trade = Timestamp::from_timestamp_millis(1_150_000);
+        host.state_mut().record_trade_price(&market, Amount::from_ccd(20), earlier_trade);
+        let parameter_bytes = to_bytes(&0u64);
+        let ctx = admin_context(ADDRESS_0, 1_200_000, &parameter_bytes);
+        let mut logger = TestLogger::init();
+        claim!(settle_auction(&ctx, &mut host, &mut logger).is_ok(), "Settling is rejected");
+        claim_eq!(
+            logger.logs,
+            vec![to_bytes(&DexEvent::MarketHalted(MarketHaltedEvent {
+                token_id: token_id(1),
+                contract: CIS_CONTRACT_ADDRESS,
+                price: Amount::from_ccd(10),
+                reference_price: Amount::from_ccd(20),
+                halted_until: Timestamp::from_timestamp_millis(1_800_000),
+            }))]
+        );
+    }
+
+    #[concordium_test]
+    fn consult_averages_prices_over_time() {
+        let mut host = host_with_listing();
+        let market = TokenInfo {
+            id: token_id(1),
+            address: CIS_CONTRACT_ADDRESS,
+        };
+        let at = Timestamp::from_timestamp_millis;
+        host.state_mut()
+            .observe_price(&market, Amount::from_ccd(1), at(0));
+        host.state_mut()
+            .observe_price(&market, Amount::from_ccd(3), at(10_000));
+
+        // 1 CCD for the first 10 seconds of the window, 3 CCD for the rest.
+        claim_eq!(
+            host.state()
+                .consult(&market, Duration::from_seconds(20), at(20_000)),
+            Some(Amount::from_ccd(2))
+        );
+        claim_eq!(
+            host.state()
+                .consult(&market, Duration::from_seconds(5), at(20_000)),
+            Some(Amount::from_ccd(3))
+        );
+        claim_eq!(
+            host.state()
+                .consult(&market, Duration::from_seconds(30), at(20_000)),
+            None
+        );
+    }
+
+    #[concordium_test]
+    fn consult_includes_auction_settlements() {
+        let mut host = host_with_auction();
+        host.state_mut()
+            .auctions
+            .get_mut(&0)
+            .expect("Auction is not found")
+            .quantity = token_amount(2);
+        claim_eq!(place_bid(&mut host, ADDRESS_1, 0, Amount::from_ccd(10)), Ok(()));
+        let parameter_bytes = to_bytes(&0u64);
+        let ctx = admin_context(ADDRESS_0, 1_000_000, &parameter_bytes);
+        claim!(
+            settle_auction(&ctx, &mut host, &mut TestLogger::init()).is_ok(),
+            "Settling is rejected"
+        );
+
+        // The winning bid of 10 CCD for 2 units is a trade at 5 CCD a unit.
+        let market = TokenInfo {
+            id: token_id(1),
+            address: CIS_CONTRACT_ADDRESS,
+        };
+        claim_eq!(
+            host.state().consult(
+                &market,
+                Duration::from_seconds(10),
+                Timestamp::from_timestamp_millis(1_010_000)
+            ),
+            Some(Amount::from_ccd(5))
         );
+    }
 
+    #[concordium_test]
+    fn oracle_listing_is_priced_from_a_fresh_feed() {
+        let oracle = ContractAddress::new(5, 0);
+        let mut host = host_with_listing();
+        let market = TokenInfo {
+            id: token_id(1),
+            address: CIS_CONTRACT_ADDRESS,
+        };
+        let listing = host
+            .state()
+            .get_token(&market, &ADDRESS_0)
+            .expect("Listing is not found");
+        host.state_mut()
+            .remove_listing(&TokenOwnerInfo::from(market.clone(), &ADDRESS_0));
+        host.state_mut().list_token(&market, &ADDRESS_0, TokenPriceState {
+            mode: PriceMode::Oracle {
+                oracle,
+                feed_id: 7,
+                adjustment_basis_points: -1_000,
+                max_age: Duration::from_seconds(60),
+            },
+            ..listing
+        });
         TestHost::setup_mock_entrypoint(
             &mut host,
-            CIS_CONTRACT_ADDRESS,
-            OwnedEntrypointName::new_unchecked("operatorOf".to_string()),
-            MockFn::new_v1(mock_is_operator_of),
+            oracle,
+            OwnedEntrypointName::new_unchecked("getPrice".to_string()),
+            returning(|| OraclePrice {
+                price: Amount::from_ccd(2),
+                updated_at: Timestamp::from_timestamp_millis(0),
+            }),
         );
-
         TestHost::setup_mock_entrypoint(
             &mut host,
             CIS_CONTRACT_ADDRESS,
-            OwnedEntrypointName::new_unchecked("balanceOf".to_string()),
-            MockFn::new_v1(mock_balance_of),
+            OwnedEntrypointName::new_unchecked("transfer".to_string()),
+            returning(|| ()),
         );
 
-        let res = add(&ctx, &mut host);
-
-        claim!(res.is_ok(), "Results in rejection");
+        // The feed price of 2 CCD less the seller's 10% discount.
+        let parameter_bytes = to_bytes(&buy_params());
+        let mut ctx = buy_context(&parameter_bytes);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                transfer(&ctx, host, Amount::from_ccd(1), &mut TestLogger::init(), &crypto())
+            }),
+            Err(DexError::InvalidAmountPaid)
+        );
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(61_000));
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                let mut logger = TestLogger::init();
+                transfer(&ctx, host, Amount::from_micro_ccd(1_800_000), &mut logger, &crypto())
+            }),
+            Err(DexError::StalePrice)
+        );
+        ctx.set_metadata_slot_time(Timestamp::from_timestamp_millis(60_000));
+        let mut logger = TestLogger::init();
         claim!(
-            host.state().token_prices.iter().count() != 0,
-            "Token not added"
+            transfer(&ctx, &mut host, Amount::from_micro_ccd(1_800_000), &mut logger, &crypto())
+                .is_ok(),
+            "Purchase at the adjusted feed price is rejected"
         );
-        
-        
+    }
 
-        let token_list_tuple = host
-            .state().add(
-                &TokenInfo {
-                    id: token_id_1,
-                    address: CIS_CONTRACT_ADDRESS,
-                },
-                &ACCOUNT_0,
-            )
-            .expect("Should not be None");
+    #[concordium_test]
+    fn cancelled_and_expired_orders_cannot_be_filled() {
+        let mut host = host_with_listing();
+        let maker = Address::Account(ACCOUNT_0.get_alias_unchecked(3));
 
-        
+        let parameter_bytes = to_bytes(&5u64);
+        let ctx = admin_context(maker, 0, &parameter_bytes);
+        claim!(cancel_orders(&ctx, &mut host).is_ok(), "Cancelling orders is rejected");
+        claim_eq!(host.state().min_nonce_of(&ACCOUNT_0), 5);
         claim_eq!(
-            token_list_tuple.1.to_owned(),
-            TokenPriceState {
-                price,
-                quantity: token_quantity_1
+            call_rolled_back(&mut host, |host| cancel_orders(&ctx, host)),
+            Err(DexError::InvalidNonce)
+        );
+
+        let mut params = FillOrderParams {
+            order: Order {
+                maker: ACCOUNT_0,
+                cis_contract_address: CIS_CONTRACT_ADDRESS,
+                token_id: token_id(1),
+                price: Amount::from_ccd(1),
+                quantity: token_amount(2),
+                expiry: Timestamp::from_timestamp_millis(1_000),
+                nonce: 4,
             },
-        )
+            signatures: AccountSignatures {
+                sigs: std::collections::BTreeMap::new(),
+            },
+            to: Receiver::Account(ACCOUNT_1),
+            quantity: token_amount(1),
+            attestation: None,
+            maker_attestation: None,
+        };
+        let parameter_bytes = to_bytes(&params);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                fill_order(
+                    &buy_context(&parameter_bytes),
+                    host,
+                    Amount::from_ccd(1),
+                    &mut TestLogger::init(),
+                    &crypto(),
+                )
+            }),
+            Err(DexError::OrderCancelled)
+        );
+
+        params.order.nonce = 5;
+        params.order.expiry = Timestamp::from_timestamp_millis(0);
+        let parameter_bytes = to_bytes(&params);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                fill_order(
+                    &buy_context(&parameter_bytes),
+                    host,
+                    Amount::from_ccd(1),
+                    &mut TestLogger::init(),
+                    &crypto(),
+                )
+            }),
+            Err(DexError::OrderExpired)
+        );
+
+        // The maker is held to the identity policy like the seller of a
+        // listing.
+        params.order.expiry = Timestamp::from_timestamp_millis(1_000);
+        host.state_mut().identity_policy = Some(IdentityPolicy {
+            minimum_age: 18,
+            allowed_residence: vec![],
+            blocked_residence: vec![],
+            allowed_nationalities: vec![],
+        });
+        host.state_mut()
+            .apply_action(DexAction::SetVerifier(ACCOUNT_1, true));
+        let claims_bytes = to_bytes(&IdentityClaims {
+            subject: ADDRESS_1,
+            minimum_age: 18,
+            country_of_residence: *b"DE",
+            nationality: *b"DE",
+            expires_at: Timestamp::from_timestamp_millis(1_000),
+        });
+        claim!(
+            post_attestation(&admin_context(ADDRESS_1, 0, &claims_bytes), &mut host).is_ok(),
+            "Posting the attestation of the receiver is rejected"
+        );
+        let parameter_bytes = to_bytes(&params);
+        claim_eq!(
+            call_rolled_back(&mut host, |host| {
+                fill_order(
+                    &buy_context(&parameter_bytes),
+                    host,
+                    Amount::from_ccd(1),
+                    &mut TestLogger::init(),
+                    &crypto(),
+                )
+            }),
+            Err(DexError::AttestationRequired)
+        );
     }
 }
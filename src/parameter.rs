@@ -1,42 +1,352 @@
+use concordium_cis2::Receiver;
 use concordium_std::{
-    AccountAddress, Amount, ContractAddress, Deserial, SchemaType, Serial, Serialize,
+    AccountAddress, AccountSignatures, Address, Amount, ContractAddress, Deserial, Duration,
+    SchemaType, Serial, Serialize, Timestamp,
 };
 
-use crate::{state::TokenListItem, ContractTokenAmount, ContractTokenId};
+use crate::{
+    state::{
+        CollectionInfo, Currency, IdentityClaims, IdentityPolicy, PriceMode, QueuedAdminAction,
+        Royalty, TokenListItem,
+    },
+    ContractTokenAmount, ContractTokenId,
+};
 
 /// Parameters for the `add` method for Market Contract.
 #[derive(Serial, Deserial, SchemaType)]
-pub(crate) struct AddParams {
+pub struct AddParams {
     pub cis_contract_address: ContractAddress,
     pub token_id: ContractTokenId,
 
     /// Price per Unit of Token at this the Token is to be sold.
+    /// For a Dutch auction this is the starting price.
+    /// Given in micro-units of `currency`.
     pub price: Amount,
 
+    /// Currency of the price. Euro prices are converted to CCD at the time of
+    /// purchase.
+    pub currency: Currency,
+
+    /// Pricing rule applied when the token is bought.
+    pub mode: PriceMode,
+
     /// Quantity of the token which can be listed on the marketplace
     /// In case of an NFT this will always be one
     pub quantity: ContractTokenAmount,
+
+    /// Time after which the listing can no longer be bought and may be
+    /// pruned by anyone. `None` lists the token until it is sold.
+    pub expires_at: Option<Timestamp>,
+
+    /// Identity claims of the sender, needed if the market or the collection
+    /// has an identity policy.
+    pub attestation: Option<SignedAttestation>,
 }
 
 /// Parameters for the `transfer` method for Market Contract.
 #[derive(Serial, Deserial, SchemaType)]
-pub(crate) struct TransferParams {
+pub struct TransferParams {
     /// Address of the CIS2 Contract. Contract containing token to be transferred.
     pub cis_contract_address: ContractAddress,
 
     /// Token ID of the token to be transferred.
     pub token_id: ContractTokenId,
 
-    /// Address of the receiver of the token.
-    pub to: AccountAddress,
+    /// Receiver of the token. A contract receiver is called back with the
+    /// given entrypoint on delivery.
+    pub to: Receiver,
 
     /// Current owner of the Token.
-    pub owner: AccountAddress,
+    pub owner: Address,
 
     /// Quantity of the token to be transferred.
     pub quantity: ContractTokenAmount,
+
+    /// Account which referred the trader, credited with a share of the
+    /// commission.
+    pub referrer: Option<AccountAddress>,
+
+    /// Identity claims of the trader, needed if the market or the collection
+    /// has an identity policy. The trader is the receiver of the token for
+    /// `transfer`, and the owner for `transfer_cis2`.
+    pub attestation: Option<SignedAttestation>,
 }
 
+/// Identity claims signed by a verifier.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct SignedAttestation {
+    /// Account of the verifier, which signed the serialized `claims` with a
+    /// wallet.
+    pub verifier: AccountAddress,
+    pub claims: IdentityClaims,
+    pub signatures: AccountSignatures,
+}
+
+/// An offer to sell tokens, signed off-chain by the seller and settled by
+/// `fillOrder`. The seller signs the address of the Dex followed by the
+/// serialized order with a wallet.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct Order {
+    /// Account selling the tokens, which signs the order.
+    pub maker: AccountAddress,
+    pub cis_contract_address: ContractAddress,
+    pub token_id: ContractTokenId,
+
+    /// Price per unit, in CCD.
+    pub price: Amount,
+
+    /// Most units which can be bought through the order, over all fills.
+    pub quantity: ContractTokenAmount,
+
+    /// Time after which the order can no longer be filled.
+    pub expiry: Timestamp,
+
+    /// The order is cancelled once the maker raises their minimum nonce above
+    /// this.
+    pub nonce: u64,
+}
+
+/// Parameters for the `fillOrder` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct FillOrderParams {
+    pub order: Order,
+
+    /// Signatures of the maker on the order.
+    pub signatures: AccountSignatures,
+
+    /// Receiver of the token. A contract receiver is called back with the
+    /// given entrypoint on delivery.
+    pub to: Receiver,
+
+    /// Quantity of the token to be bought.
+    pub quantity: ContractTokenAmount,
+
+    /// Identity claims of the receiver, needed if the market or the collection
+    /// has an identity policy.
+    pub attestation: Option<SignedAttestation>,
+
+    /// Identity claims of the maker, needed if the market or the collection
+    /// has an identity policy and the maker has not posted claims to the
+    /// registry.
+    pub maker_attestation: Option<SignedAttestation>,
+}
+
+/// Identifies a listing, for the `delistStale` and `buybackCapacity` methods
+/// for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct ListingParams {
+    /// Address of the CIS2 Contract of the listed token.
+    pub cis_contract_address: ContractAddress,
+
+    /// Token ID of the listed token.
+    pub token_id: ContractTokenId,
+
+    /// Seller of the listed token.
+    pub owner: Address,
+}
+
+/// Identifies a market, i.e. a single token, for the `viewMarket` and
+/// `viewIdentityPolicies` methods for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct MarketParams {
+    pub cis_contract_address: ContractAddress,
+    pub token_id: ContractTokenId,
+}
+
+/// Parameters for the `consult` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct ConsultParams {
+    pub cis_contract_address: ContractAddress,
+    pub token_id: ContractTokenId,
+
+    /// Period before now to average the price over.
+    pub window: Duration,
+}
+
+/// Parameters for the `pruneExpired` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct PruneParams {
+    /// Number of the first listing to scan. Listings are numbered in the order
+    /// they were listed, counting only listings with an expiry.
+    pub start: u64,
+    /// Most listings to scan, expired or not.
+    pub max_scanned: u32,
+}
+
+/// Parameters for the `quote` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct QuoteParams {
+    pub cis_contract_address: ContractAddress,
+    pub token_id: ContractTokenId,
+    pub owner: Address,
+    pub quantity: ContractTokenAmount,
+}
+
+/// Return value of the `getPrice` method of a price oracle, queried with the
+/// `u64` id of a feed.
+#[derive(Serialize, SchemaType)]
+pub struct OraclePrice {
+    /// Price per unit, in micro-units of the currency of the listing.
+    pub price: Amount,
+    pub updated_at: Timestamp,
+}
+
+/// Return value of the `quote` method for Market Contract.
+#[derive(Serialize, SchemaType)]
+pub struct Quote {
+    /// Current price per unit, in micro-units of `currency`.
+    pub unit_price: Amount,
+    pub currency: Currency,
+    /// Current price per unit in CCD.
+    pub unit_price_ccd: Amount,
+    /// CCD to be paid for the quoted quantity.
+    pub total_ccd: Amount,
+}
+
+/// Parameters for the `createAuction` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct CreateAuctionParams {
+    pub cis_contract_address: ContractAddress,
+    pub token_id: ContractTokenId,
+
+    /// Quantity of the token which is auctioned as a single lot.
+    pub quantity: ContractTokenAmount,
+
+    /// Lowest acceptable first bid.
+    pub reserve_price: Amount,
+
+    /// Amount by which each bid must exceed the previous highest bid.
+    pub min_increment: Amount,
+
+    /// Time from which bids are accepted.
+    pub start: Timestamp,
+
+    /// Time at which the auction ends, unless extended by a late bid.
+    pub end: Timestamp,
+
+    /// Where the token goes back to if it is not sold. Must be the seller, and
+    /// names the entrypoint on which a contract seller receives the token.
+    pub return_to: Receiver,
+
+    /// Identity claims of the seller, needed if the market or the collection
+    /// has an identity policy.
+    pub attestation: Option<SignedAttestation>,
+}
+
+/// Parameters for the `bid` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct BidParams {
+    pub auction_id: u64,
+
+    /// Receiver of the token if the bid wins.
+    pub to: Receiver,
+
+    /// Identity claims of the receiver of the token, needed if the market or
+    /// the collection has an identity policy.
+    pub attestation: Option<SignedAttestation>,
+}
+
+/// Parameters for the `registerCollection` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct RegisterCollectionParams {
+    /// Address of the CIS2 Contract to approve.
+    pub cis_contract_address: ContractAddress,
+
+    pub info: CollectionInfo,
+}
+
+/// Parameters for the `setRoyalty` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct SetRoyaltyParams {
+    /// Address of the CIS2 Contract the royalty applies to.
+    pub cis_contract_address: ContractAddress,
+
+    /// `None` removes the royalty.
+    pub royalty: Option<Royalty>,
+}
+
+/// Parameters for the `setCollectionPolicy` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct SetCollectionPolicyParams {
+    /// Address of the CIS2 Contract the policy applies to.
+    pub cis_contract_address: ContractAddress,
+
+    /// `None` removes the policy.
+    pub policy: Option<IdentityPolicy>,
+}
+
+/// Parameters for the `setTokenPolicy` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct SetTokenPolicyParams {
+    /// Address of the CIS2 Contract of the token.
+    pub cis_contract_address: ContractAddress,
+    pub token_id: ContractTokenId,
+
+    /// `None` removes the policy.
+    pub policy: Option<IdentityPolicy>,
+}
+
+/// Parameters for the `checkEligibility` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct EligibilityQuery {
+    /// Address of the CIS2 Contract whose tokens are traded.
+    pub cis_contract_address: ContractAddress,
+    pub token_id: ContractTokenId,
+
+    pub address: Address,
+}
+
+/// Parameters for the `setVerifier` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct SetVerifierParams {
+    pub verifier: AccountAddress,
+
+    /// Whether claims signed by the verifier are trusted.
+    pub trusted: bool,
+}
+
+/// Return value of the `viewCollections` method for Market Contract.
+#[derive(Serialize, SchemaType)]
+pub struct CollectionRegistry {
+    /// When set, collections do not need to be approved to be traded.
+    pub permissionless: bool,
+
+    #[concordium(size_length = 2)]
+    pub collections: Vec<(ContractAddress, CollectionInfo)>,
+}
+
+/// Parameters for the `vote` method for Market Contract.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct VoteParams {
+    pub proposal_id: u64,
+
+    /// `true` to vote for the proposal, `false` to vote against it.
+    pub support: bool,
+}
+
+/// A query for the balance of an address at a past time, sent to the
+/// `balanceOfAt` method of `techFiestaToken`.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct BalanceOfAtQuery {
+    pub token_id: ContractTokenId,
+    pub address: Address,
+    pub time: Timestamp,
+}
+
+/// Parameters for the `balanceOfAt` method of `techFiestaToken`.
+#[derive(Serial, Deserial, SchemaType)]
+pub struct BalanceOfAtQueryParams {
+    #[concordium(size_length = 2)]
+    pub queries: Vec<BalanceOfAtQuery>,
+}
+
+/// Return value of the `viewAdminQueue` method for Market Contract.
+#[derive(Debug, Serialize, SchemaType)]
+pub struct AdminQueue(
+    #[concordium(size_length = 2)]
+    pub Vec<(u64, QueuedAdminAction<ContractTokenId, ContractTokenAmount>)>,
+);
+
 #[derive(Debug, Serialize, SchemaType)]
 pub struct TokenList(
     #[concordium(size_length = 2)] pub Vec<TokenListItem<ContractTokenId, ContractTokenAmount>>,
@@ -46,4 +356,8 @@ pub struct TokenList(
 #[derive(Serial, Deserial, SchemaType)]
 pub struct InitParams {
     pub amount: u16,
+
+    /// Collections approved for trading from the start. Later registrations
+    /// wait for the admin delay.
+    pub collections: Vec<RegisterCollectionParams>,
 }
\ No newline at end of file
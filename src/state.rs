@@ -1,7 +1,7 @@
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
-use concordium_cis2::{IsTokenAmount, IsTokenId};
+use concordium_cis2::{IsTokenAmount, IsTokenId, Receiver};
 use concordium_std::*;
 
 #[derive(Clone, Serialize, PartialEq, Eq, Debug)]
@@ -14,47 +14,676 @@ pub struct TokenInfo<T: IsTokenId> {
 pub struct TokenOwnerInfo<T: IsTokenId> {
     pub id: T,
     pub address: ContractAddress,
-    pub owner: AccountAddress,
+    pub owner: Address,
+}
+
+/// The address all aliases of an account are keyed under: the alias whose last
+/// three bytes are zero.
+pub fn canonical(account: &AccountAddress) -> AccountAddress { account.get_alias_unchecked(0) }
+
+/// The canonical alias of an account address. Contract addresses are
+/// returned unchanged.
+pub fn canonical_address(address: &Address) -> Address {
+    match address {
+        Address::Account(account) => Address::Account(canonical(account)),
+        Address::Contract(contract) => Address::Contract(*contract),
+    }
 }
 
 impl<T: IsTokenId> TokenOwnerInfo<T> {
-    pub fn from(token_info: TokenInfo<T>, owner: &AccountAddress) -> Self {
+    /// Keys a listing by the canonical alias of its owner, so that every alias
+    /// of the owner refers to the same listing.
+    pub fn from(token_info: TokenInfo<T>, owner: &Address) -> Self {
         TokenOwnerInfo {
-            owner: *owner,
+            owner: canonical_address(owner),
             id: token_info.id,
             address: token_info.address,
         }
     }
 }
 
+/// How the price of a listed token is determined at the time of purchase.
+#[derive(Clone, Serialize, SchemaType, Copy, PartialEq, Eq, Debug)]
+pub enum PriceMode {
+    /// Every unit is sold at the listed price.
+    Fixed,
+    /// The unit price decreases linearly from the listed price to `end_price`
+    /// over `duration`, counted from the time of listing.
+    Dutch {
+        end_price: Amount,
+        duration: Duration,
+    },
+    /// The unit price is read from a price feed of an oracle contract at the
+    /// time of purchase, and raised by a premium or lowered by a discount. The
+    /// purchase is rejected if the feed was last updated more than `max_age`
+    /// ago. The listed price is only shown by `list`.
+    Oracle {
+        oracle: ContractAddress,
+        feed_id: u64,
+        /// Premium if positive, discount if negative, in basis points of the
+        /// feed price.
+        adjustment_basis_points: i32,
+        max_age: Duration,
+    },
+}
+
+/// Currency in which the price of a listing is denominated.
+#[derive(Clone, Serialize, SchemaType, Copy, PartialEq, Eq, Debug)]
+pub enum Currency {
+    /// Prices are in microCCD.
+    Ccd,
+    /// Prices are in micro-euro, and converted to CCD at the time of purchase.
+    Eur,
+}
+
+/// Converts a price in micro-euro to CCD at the exchange rate
+/// `micro_ccd_per_euro`, rounding up. Returns `None` if the result overflows.
+pub fn euro_to_ccd(micro_euro: u64, micro_ccd_per_euro: ExchangeRate) -> Option<Amount> {
+    let numerator = u128::from(micro_euro) * u128::from(micro_ccd_per_euro.numerator());
+    let denominator = u128::from(micro_ccd_per_euro.denominator()) * 1_000_000;
+    let micro_ccd = numerator.div_ceil(denominator);
+    u64::try_from(micro_ccd).ok().map(Amount::from_micro_ccd)
+}
+
+/// Raises `price` by a premium, or lowers it by a discount, given in basis
+/// points. Returns `None` if the result does not fit in an `Amount`.
+pub fn adjust_price(price: Amount, adjustment_basis_points: i32) -> Option<Amount> {
+    let factor = u128::try_from(10_000 + i64::from(adjustment_basis_points)).ok()?;
+    let micro_ccd = u128::from(price.micro_ccd) * factor / 10_000;
+    u64::try_from(micro_ccd).ok().map(Amount::from_micro_ccd)
+}
+
+/// Move from `reference` to `price`, in basis points of `reference`.
+pub fn price_move_basis_points(reference: Amount, price: Amount) -> u128 {
+    let change = u128::from(reference.micro_ccd.abs_diff(price.micro_ccd));
+    match reference.micro_ccd {
+        0 if change == 0 => 0,
+        0 => u128::MAX,
+        reference => change * 10_000 / u128::from(reference),
+    }
+}
+
 #[derive(Clone, Serialize, Copy, PartialEq, Eq, Debug)]
 pub struct TokenPriceState<A: IsTokenAmount> {
     pub quantity: A,
+    /// Price per unit, in micro-units of `currency`.
     pub price: Amount,
+    pub currency: Currency,
+    pub mode: PriceMode,
+    pub listed_at: Timestamp,
+    /// Time after which the listing is treated as absent.
+    pub expires_at: Option<Timestamp>,
+    /// The address which listed the tokens, with the alias it used. CIS2
+    /// contracts which do not canonicalize aliases only know the tokens under
+    /// this alias.
+    pub holder: Address,
+}
+
+impl<A: IsTokenAmount> TokenPriceState<A> {
+    /// Whether the listing has expired at time `now`.
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Price of a single unit of the token at time `now`, in micro-units of
+    /// the listing's currency.
+    pub fn unit_price(&self, now: Timestamp) -> Amount {
+        match self.mode {
+            PriceMode::Fixed | PriceMode::Oracle { .. } => self.price,
+            PriceMode::Dutch {
+                end_price,
+                duration,
+            } => {
+                let elapsed = now.duration_since(self.listed_at).map_or(0, |d| d.millis());
+                let duration = duration.millis();
+                if elapsed >= duration {
+                    return end_price;
+                }
+                let max_decay = self.price.micro_ccd.saturating_sub(end_price.micro_ccd);
+                let decay = u128::from(max_decay) * u128::from(elapsed) / u128::from(duration);
+                Amount::from_micro_ccd(self.price.micro_ccd - decay as u64)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
 pub struct TokenListItem<T: IsTokenId, A: IsTokenAmount> {
     pub token_id: T,
     pub contract: ContractAddress,
+    /// Current price per unit, in micro-units of `currency`.
     pub price: Amount,
-    pub owner: AccountAddress,
+    pub currency: Currency,
+    pub owner: Address,
     pub quantity: A,
 }
 
+/// The highest bid placed on an auction so far.
+#[derive(Debug, Serialize, SchemaType, Clone)]
+pub struct Bid {
+    /// Account or contract which placed the bid, and is refunded if outbid.
+    pub bidder: Address,
+    /// Receiver of the token if the bid wins.
+    pub to: Receiver,
+    pub amount: Amount,
+}
+
+/// An English auction. The auctioned tokens are held by the Dex until the
+/// auction is settled.
+#[derive(Debug, Serialize, SchemaType, Clone)]
+pub struct AuctionState<T: IsTokenId, A: IsTokenAmount> {
+    pub seller: Address,
+    /// Where the token goes back to if it is not sold.
+    pub return_to: Receiver,
+    pub contract: ContractAddress,
+    pub token_id: T,
+    pub quantity: A,
+    /// Lowest acceptable first bid.
+    pub reserve_price: Amount,
+    /// Amount by which each bid must exceed the previous highest bid.
+    pub min_increment: Amount,
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub highest_bid: Option<Bid>,
+}
+
+/// Daily caps on the CCD the Dex spends buying tokens through
+/// `transfer_cis2`.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub struct BuybackLimits {
+    /// Cap on spend per token per day.
+    pub per_token: Amount,
+    /// Cap on spend per selling account per day.
+    pub per_account: Amount,
+    /// Cap on total spend per day.
+    pub per_day: Amount,
+}
+
+/// Buyback spend within a single day.
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+pub struct DailySpend {
+    /// Day number, counted in whole days since the Unix epoch.
+    pub day: u64,
+    pub spent: Amount,
+}
+
+impl DailySpend {
+    /// Amount spent on `day`.
+    pub fn spent_on(&self, day: u64) -> Amount {
+        if self.day == day {
+            self.spent
+        } else {
+            Amount::zero()
+        }
+    }
+
+    /// Records spending `amount` on `day`.
+    fn record(&mut self, day: u64, amount: Amount) {
+        self.spent = self.spent_on(day) + amount;
+        self.day = day;
+    }
+}
+
+/// Remaining buyback capacity for a token and selling account on the current
+/// day.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub struct BuybackCapacity {
+    pub reserve: Amount,
+    pub per_token: Amount,
+    pub per_account: Amount,
+    pub per_day: Amount,
+    /// The most which can be spent on a single buyback, the minimum of the
+    /// above.
+    pub available: Amount,
+}
+
+/// Metadata of an approved CIS2 collection, shown by frontends next to its
+/// tokens.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub struct CollectionInfo {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+    /// Owner of the CIS2 contract instance, as reported by the node.
+    /// Contracts cannot look up the owner of another instance, so it is
+    /// recorded here when the collection is registered.
+    pub owner: Option<AccountAddress>,
+}
+
+/// Royalty paid to the creator of a collection on every sale of its tokens.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub struct Royalty {
+    /// Share of the sale price, in basis points.
+    pub basis_points: u16,
+    pub recipient: AccountAddress,
+}
+
+/// A raise of the royalty of a collection, waiting for the admin delay to
+/// pass so that sellers can delist before it applies.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub struct RoyaltyChange {
+    pub royalty: Royalty,
+    pub effective_at: Timestamp,
+}
+
+/// Return value of the `pruneExpired` method for Market Contract.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub struct PruneResult {
+    /// Number of listings removed.
+    pub removed: u32,
+    /// Where to continue scanning, or `None` if the scan reached the end.
+    pub next: Option<u64>,
+}
+
+/// Trading fees of the marketplace.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub struct FeeConfig {
+    /// Commission taken by the marketplace on every sale, in basis points of
+    /// the sale price.
+    pub commission_basis_points: u16,
+    /// Share of the commission credited to the referrer of a trade, in basis
+    /// points of the commission.
+    pub referral_share_basis_points: u16,
+}
+
+/// Rewards earned by a referrer.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub struct ReferralStats {
+    /// Number of trades made with this account as the referrer.
+    pub trades: u64,
+    /// Total referral rewards credited to this account.
+    pub earned: Amount,
+}
+
+/// Country code in ISO 3166-1 alpha-2 format, e.g. `*b"DE"`.
+pub type CountryCode = [u8; 2];
+
+/// Claims about the identity behind an address, as proven to a verifier with
+/// Concordium ID 2.0 and signed by the verifier.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub struct IdentityClaims {
+    pub subject: Address,
+    /// The subject is at least this many years old.
+    pub minimum_age: u8,
+    pub country_of_residence: CountryCode,
+    pub nationality: CountryCode,
+    /// Time after which the claims can no longer be used.
+    pub expires_at: Timestamp,
+}
+
+/// Identity claims an address needs to trade in a market. Empty lists do not
+/// restrict anything.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub struct IdentityPolicy {
+    pub minimum_age: u8,
+    #[concordium(size_length = 2)]
+    pub allowed_residence: Vec<CountryCode>,
+    #[concordium(size_length = 2)]
+    pub blocked_residence: Vec<CountryCode>,
+    #[concordium(size_length = 2)]
+    pub allowed_nationalities: Vec<CountryCode>,
+}
+
+impl IdentityPolicy {
+    /// Checks the claims against this policy. Expiry of the claims is checked
+    /// by the caller.
+    pub fn check(&self, claims: &IdentityClaims) -> Result<(), IneligibleReason> {
+        let residence = &claims.country_of_residence;
+        if claims.minimum_age < self.minimum_age {
+            return Err(IneligibleReason::BelowMinimumAge);
+        }
+        if (!self.allowed_residence.is_empty() && !self.allowed_residence.contains(residence))
+            || self.blocked_residence.contains(residence)
+        {
+            return Err(IneligibleReason::ResidenceNotAllowed);
+        }
+        if !self.allowed_nationalities.is_empty()
+            && !self.allowed_nationalities.contains(&claims.nationality)
+        {
+            return Err(IneligibleReason::NationalityNotAllowed);
+        }
+        Ok(())
+    }
+}
+
+/// Why an address does not meet the identity policies of a market.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub enum IneligibleReason {
+    /// No verifier has posted claims about the address.
+    NotAttested,
+    /// The verifier which posted the claims is no longer trusted.
+    UnknownVerifier,
+    Revoked,
+    Expired,
+    BelowMinimumAge,
+    ResidenceNotAllowed,
+    NationalityNotAllowed,
+}
+
+/// Identity claims posted to the attestation registry by a verifier.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub struct AttestationRecord {
+    pub verifier: AccountAddress,
+    pub claims: IdentityClaims,
+    /// Set when the verifier revokes the claims before they expire.
+    pub revoked: bool,
+}
+
+/// Limits on how far the price in a market can move before trading in it
+/// halts. A market is a single token of a CIS2 contract.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub struct CircuitBreakerConfig {
+    /// Largest move from the reference price, in basis points.
+    pub max_move_basis_points: u16,
+    /// How long the price of a trade stays the reference price.
+    pub window: Duration,
+    /// How long trading halts once the breaker trips.
+    pub cooldown: Duration,
+}
+
+/// Circuit breaker of a market.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub struct MarketBreaker {
+    /// CCD price per unit of the trade which started the current window.
+    pub reference_price: Amount,
+    pub reference_time: Timestamp,
+    /// Set when the breaker trips. Trading resumes at this time.
+    pub halted_until: Option<Timestamp>,
+}
+
+/// Number of trades whose prices are kept per market for `consult`.
+pub const MAX_PRICE_OBSERVATIONS: usize = 64;
+
+/// The price of a market from a trade until the next one.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone, Copy)]
+pub struct PriceObservation {
+    pub time: Timestamp,
+    /// CCD price per unit of the trade.
+    pub price: Amount,
+    /// Sum of the price in micro CCD times the milliseconds it held, from the
+    /// first trade in the market up to `time`.
+    pub cumulative: u128,
+}
+
+impl PriceObservation {
+    /// The cumulative price at a time at or after this observation, before
+    /// the next one.
+    fn cumulative_at(&self, time: Timestamp) -> u128 {
+        let elapsed = time.duration_since(self.time).map_or(0, |d| d.millis());
+        self.cumulative + u128::from(self.price.micro_ccd) * u128::from(elapsed)
+    }
+}
+
+/// A change to the marketplace parameters, applied by an executed governance
+/// proposal.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub enum DexAction {
+    SetFees(FeeConfig),
+    RegisterCollection(ContractAddress, CollectionInfo),
+    RemoveCollection(ContractAddress),
+    SetPermissionless(bool),
+    SetPaused(bool),
+    SetContractPrincipals(bool),
+    SetIdentityPolicy(Option<IdentityPolicy>),
+    SetVerifier(AccountAddress, bool),
+    SetCircuitBreaker(Option<CircuitBreakerConfig>),
+}
+
+/// Parameters of token-holder governance.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub struct GovernanceConfig<T: IsTokenId, A: IsTokenAmount> {
+    /// The `techFiestaToken` instance whose balances are the voting weight.
+    pub token: ContractAddress,
+    pub token_id: T,
+    /// How long a proposal is open for voting.
+    pub voting_period: Duration,
+    /// Delay between the end of voting and the earliest execution.
+    pub timelock: Duration,
+    /// Minimum total weight of the votes cast for a proposal to pass.
+    pub quorum: A,
+    /// Share of the votes cast that must be in favour, in basis points. A
+    /// proposal passes only if its share is above the threshold.
+    pub threshold_basis_points: u16,
+}
+
+/// An M-of-N set of accounts controlling the privileged entrypoints.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub struct MultisigConfig {
+    #[concordium(size_length = 1)]
+    pub signers: Vec<AccountAddress>,
+    /// Number of signers which must approve an admin action.
+    pub threshold: u8,
+}
+
+impl MultisigConfig {
+    /// Whether the threshold is between one and the number of signers, and no
+    /// account is listed twice, not even under different aliases.
+    pub fn is_valid(&self) -> bool {
+        let distinct = self
+            .signers
+            .iter()
+            .enumerate()
+            .all(|(i, signer)| !self.signers[..i].iter().any(|s| s.is_alias(signer)));
+        self.threshold > 0 && usize::from(self.threshold) <= self.signers.len() && distinct
+    }
+
+    pub fn is_signer(&self, account: &AccountAddress) -> bool {
+        self.signers.iter().any(|signer| signer.is_alias(account))
+    }
+
+    /// Whether enough of the current signers are among `approvals`.
+    pub fn is_approved(&self, approvals: &[AccountAddress]) -> bool {
+        approvals.iter().filter(|a| self.is_signer(a)).count() >= usize::from(self.threshold)
+    }
+}
+
+/// Upgrade of the contract instance to a new smart contract module.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub struct UpgradeParams {
+    pub module: ModuleReference,
+    /// Entrypoint of the new module to call after the upgrade, with its
+    /// parameter.
+    pub migrate: Option<(OwnedEntrypointName, OwnedParameter)>,
+}
+
+/// A privileged action, run once approved by the multisig signers.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub enum AdminAction<T: IsTokenId, A: IsTokenAmount> {
+    Dex(DexAction),
+    SetGovernance(GovernanceConfig<T, A>),
+    SetBuybackLimits(BuybackLimits),
+    /// Sends CCD from the buyback reserve to the owner of the instance.
+    WithdrawReserve(Amount),
+    SetAdmins(MultisigConfig),
+    /// Sets how long admin actions wait in the queue.
+    SetAdminDelay(Duration),
+    Upgrade(UpgradeParams),
+    /// Sets the account which manages the blocklist.
+    SetCompliance(Option<AccountAddress>),
+}
+
+/// An admin action waiting for approvals.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub struct AdminProposal<T: IsTokenId, A: IsTokenAmount> {
+    pub action: AdminAction<T, A>,
+    #[concordium(size_length = 1)]
+    pub approvals: Vec<AccountAddress>,
+}
+
+/// An approved admin action waiting for its delay to pass.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub struct QueuedAdminAction<T: IsTokenId, A: IsTokenAmount> {
+    pub action: AdminAction<T, A>,
+    pub executable_at: Timestamp,
+}
+
+/// A governance proposal.
+#[derive(Debug, Serialize, SchemaType, PartialEq, Eq, Clone)]
+pub struct Proposal<A: IsTokenAmount> {
+    pub proposer: AccountAddress,
+    pub action: DexAction,
+    /// Voting weight is the token balance held just before this time.
+    pub snapshot: Timestamp,
+    pub voting_ends: Timestamp,
+    /// Earliest time at which the proposal can be executed.
+    pub executable_at: Timestamp,
+    pub votes_for: A,
+    pub votes_against: A,
+    pub executed: bool,
+}
+
+/// Day number of `time`, counted in whole days since the Unix epoch.
+fn day_of(time: Timestamp) -> u64 {
+    time.timestamp_millis() / Duration::from_days(1).millis()
+}
+
 #[derive(Serial, DeserialWithState)]
 #[concordium(state_parameter = "S")]
 pub struct State<S: HasStateApi, T: IsTokenId, A: IsTokenAmount + Copy> {
     pub token_prices: StateMap<TokenOwnerInfo<T>, TokenPriceState<A>, S>,
+    /// Listings with an expiry, numbered in the order they were listed, so
+    /// that `prune_expired` can find expired listings without scanning all of
+    /// them. Entries stay until they are scanned, even once their listing is
+    /// gone.
+    pub expiring_listings: StateMap<u64, TokenOwnerInfo<T>, S>,
+    pub next_expiring_listing: u64,
+    pub auctions: StateMap<u64, AuctionState<T, A>, S>,
+    pub next_auction_id: u64,
+    /// Set while a mutating entrypoint is running, to reject reentrant calls.
+    pub locked: bool,
+    /// Sale proceeds owed to each address, keyed by its canonical alias and
+    /// paid out on withdrawal.
+    pub proceeds: StateMap<Address, Amount, S>,
+    /// Sum of all amounts in `proceeds`.
+    pub total_proceeds: Amount,
+    /// CCD set aside by the owner for buying tokens through `transfer_cis2`.
+    pub reserve: Amount,
+    pub buyback_limits: BuybackLimits,
+    pub buyback_spent: DailySpend,
+    pub buyback_spent_per_token: StateMap<TokenInfo<T>, DailySpend, S>,
+    pub buyback_spent_per_account: StateMap<Address, DailySpend, S>,
+    /// CIS2 collections approved by the owner for trading.
+    pub collections: StateMap<ContractAddress, CollectionInfo, S>,
+    /// When set, collections do not need to be approved to be traded.
+    pub permissionless: bool,
+    /// Royalties set by collection owners.
+    pub royalties: StateMap<ContractAddress, Royalty, S>,
+    pub royalty_changes: StateMap<ContractAddress, RoyaltyChange, S>,
+    pub fees: FeeConfig,
+    pub referrals: StateMap<AccountAddress, ReferralStats, S>,
+    /// When set, no tokens can be listed, bought or auctioned.
+    pub paused: bool,
+    /// Whether smart contracts can list tokens and receive bought tokens.
+    /// Age verification is done off-chain against the identity behind an
+    /// account, which a contract does not have, so this is off by default.
+    pub allow_contract_principals: bool,
+    /// Identity claims every trader in the marketplace needs.
+    pub identity_policy: Option<IdentityPolicy>,
+    /// Further identity claims set by collection owners for trading their
+    /// tokens.
+    pub collection_policies: StateMap<ContractAddress, IdentityPolicy, S>,
+    /// Further identity claims set by collection owners for trading single
+    /// tokens of their collections.
+    pub token_policies: StateMap<TokenInfo<T>, IdentityPolicy, S>,
+    /// Canonical aliases of the accounts whose signed identity claims are
+    /// trusted.
+    pub verifiers: StateSet<AccountAddress, S>,
+    /// Identity claims posted by verifiers, keyed by the canonical alias of
+    /// their subject.
+    pub attestations: StateMap<Address, AttestationRecord, S>,
+    /// Account which adds and removes addresses on the blocklist.
+    pub compliance: Option<AccountAddress>,
+    /// Canonical aliases of the addresses flagged by compliance, which can
+    /// neither trade here nor transfer `techFiestaToken`.
+    pub blocked: StateSet<Address, S>,
+    /// When set, trading in a market halts after an abnormal price move.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    pub breakers: StateMap<TokenInfo<T>, MarketBreaker, S>,
+    /// Prices of the latest trades of each market, oldest first.
+    pub price_observations: StateMap<TokenInfo<T>, Vec<PriceObservation>, S>,
+    /// Quantity bought so far through each signed order, keyed by its hash.
+    pub order_fills: StateMap<HashSha2256, A, S>,
+    /// Orders with a lower nonce than this are cancelled, keyed by the
+    /// canonical alias of their maker.
+    pub min_nonces: StateMap<AccountAddress, u64, S>,
+    /// Once set, marketplace parameters can only be changed by governance.
+    pub governance: Option<GovernanceConfig<T, A>>,
+    pub proposals: StateMap<u64, Proposal<A>, S>,
+    pub next_proposal_id: u64,
+    /// Proposal ids paired with the canonical alias of each account which
+    /// voted on them.
+    pub votes: StateSet<(u64, AccountAddress), S>,
+    /// Once set, privileged entrypoints need the approval of these signers.
+    pub admins: Option<MultisigConfig>,
+    pub admin_proposals: StateMap<u64, AdminProposal<T, A>, S>,
+    pub next_admin_proposal_id: u64,
+    /// How long approved admin actions wait before they can run.
+    pub admin_delay: Duration,
+    pub admin_queue: StateMap<u64, QueuedAdminAction<T, A>, S>,
+    pub next_queued_admin_action_id: u64,
 }
 
-impl<S: HasStateApi, T: IsTokenId + Copy, A: IsTokenAmount + Copy + ops::Sub<Output = A>>
+impl<S: HasStateApi, T: IsTokenId + Clone, A: IsTokenAmount + Copy + ops::Sub<Output = A>>
     State<S, T, A>
 {
     /// Creates a new state with the given tokenPrices.
     pub fn new(state_builder: &mut StateBuilder<S>,) -> Self {
         State {
             token_prices: state_builder.new_map(),
+            expiring_listings: state_builder.new_map(),
+            next_expiring_listing: 0,
+            auctions: state_builder.new_map(),
+            next_auction_id: 0,
+            locked: false,
+            proceeds: state_builder.new_map(),
+            total_proceeds: Amount::zero(),
+            reserve: Amount::zero(),
+            // Buybacks are disabled until the owner sets limits.
+            buyback_limits: BuybackLimits {
+                per_token: Amount::zero(),
+                per_account: Amount::zero(),
+                per_day: Amount::zero(),
+            },
+            buyback_spent: DailySpend {
+                day: 0,
+                spent: Amount::zero(),
+            },
+            buyback_spent_per_token: state_builder.new_map(),
+            buyback_spent_per_account: state_builder.new_map(),
+            collections: state_builder.new_map(),
+            permissionless: false,
+            royalties: state_builder.new_map(),
+            royalty_changes: state_builder.new_map(),
+            fees: FeeConfig {
+                commission_basis_points: 0,
+                referral_share_basis_points: 0,
+            },
+            referrals: state_builder.new_map(),
+            paused: false,
+            allow_contract_principals: false,
+            identity_policy: None,
+            collection_policies: state_builder.new_map(),
+            token_policies: state_builder.new_map(),
+            verifiers: state_builder.new_set(),
+            attestations: state_builder.new_map(),
+            compliance: None,
+            blocked: state_builder.new_set(),
+            circuit_breaker: None,
+            breakers: state_builder.new_map(),
+            price_observations: state_builder.new_map(),
+            order_fills: state_builder.new_map(),
+            min_nonces: state_builder.new_map(),
+            governance: None,
+            proposals: state_builder.new_map(),
+            next_proposal_id: 0,
+            votes: state_builder.new_set(),
+            admins: None,
+            admin_proposals: state_builder.new_map(),
+            next_admin_proposal_id: 0,
+            // Gives users two days of notice before admin changes take effect.
+            admin_delay: Duration::from_days(2),
+            admin_queue: state_builder.new_map(),
+            next_queued_admin_action_id: 0,
         }
     }
 
@@ -62,22 +691,26 @@ impl<S: HasStateApi, T: IsTokenId + Copy, A: IsTokenAmount + Copy + ops::Sub<Out
     pub fn list_token(
         &mut self,
         token_info: &TokenInfo<T>,
-        owner: &AccountAddress,
-        price: Amount,
-        quantity: A,
+        owner: &Address,
+        listing: TokenPriceState<A>,
     ) {
-        match self.token_prices.get(&TokenOwnerInfo::from(token_info.clone(), owner)) {
-            // If the token is already listed, do nothing.
-            Some(_) => None,
-            // If the token is not listed, add it to the list.
-            None => self.token_prices.insert(
-                TokenOwnerInfo::from(token_info.clone(), owner),
-                TokenPriceState {
-                    quantity: quantity,
-                    price,
-                },
-            ),
-        };
+        let token_owner_info = TokenOwnerInfo::from(token_info.clone(), owner);
+        // If the token is already listed, do nothing. An expired listing is
+        // replaced as if it was not listed.
+        let is_listed = self
+            .token_prices
+            .get(&token_owner_info)
+            .is_some_and(|p| !p.is_expired(listing.listed_at));
+        // If the token is not listed, add it to the list.
+        if !is_listed {
+            if listing.expires_at.is_some() {
+                let _ = self
+                    .expiring_listings
+                    .insert(self.next_expiring_listing, token_owner_info.clone());
+                self.next_expiring_listing += 1;
+            }
+            let _ = self.token_prices.insert(token_owner_info, listing);
+        }
     }
 
     pub(crate) fn decrease_listed_quantity(&mut self, token_info: &TokenOwnerInfo<T>, delta: A) {
@@ -86,36 +719,489 @@ impl<S: HasStateApi, T: IsTokenId + Copy, A: IsTokenAmount + Copy + ops::Sub<Out
         }
     }
 
+    /// Removes a token from the buyable token list.
+    pub fn remove_listing(&mut self, token_info: &TokenOwnerInfo<T>) {
+        self.token_prices.remove(token_info);
+    }
+
     /// Gets a token from the buyable token list.
     pub fn get_token(
         &self,
         token_info: &TokenInfo<T>,
-        owner: &AccountAddress,
+        owner: &Address,
     ) -> Option<TokenPriceState<A>> {
-        match self.token_prices.get(&TokenOwnerInfo::from(token_info.clone(), owner)) {
-            None => None,
-            Some(r) => Some(*r),
-        }
+        self.token_prices
+            .get(&TokenOwnerInfo::from(token_info.clone(), owner))
+            .map(|r| *r)
     }
 
-    /// Gets a list of all tokens in the buyable token list.
-    pub fn list(&self) -> Vec<TokenListItem<T, A>> {
+    /// Gets a list of all unexpired tokens in the buyable token list, priced
+    /// at time `now`.
+    pub fn list(&self, now: Timestamp) -> Vec<TokenListItem<T, A>> {
         self.token_prices
             .iter()
-            .filter_map(|p| -> Option<TokenListItem<T, A>> {
-                let token_info = TokenInfo {
-                    id: p.0.id,
-                    address: p.0.address,
-                };
-
-                Option::Some(TokenListItem {
-                        token_id: token_info.id,
-                        contract: token_info.address,
-                        price: p.1.price,
-                        owner: p.0.owner,
-                        quantity: p.1.quantity,
-                    })
+            .filter(|p| !p.1.is_expired(now))
+            .map(|p| TokenListItem {
+                token_id: p.0.id.clone(),
+                contract: p.0.address,
+                price: p.1.unit_price(now),
+                currency: p.1.currency,
+                owner: p.0.owner,
+                quantity: p.1.quantity,
             })
             .collect()
     }
-}
\ No newline at end of file
+
+    /// Scans the `max_scanned` entries of `expiring_listings` from `start`,
+    /// removing the listings which have expired at time `now`.
+    pub fn prune_expired(&mut self, now: Timestamp, start: u64, max_scanned: u32) -> PruneResult {
+        let end = start
+            .saturating_add(u64::from(max_scanned))
+            .min(self.next_expiring_listing);
+        let mut removed = 0;
+        for index in start..end {
+            let token_owner_info = match self.expiring_listings.get(&index) {
+                Some(token_owner_info) => (*token_owner_info).clone(),
+                None => continue,
+            };
+            let listing = self.token_prices.get(&token_owner_info).map(|p| *p);
+            match listing {
+                Some(listing) if !listing.is_expired(now) => continue,
+                Some(_) => {
+                    self.token_prices.remove(&token_owner_info);
+                    removed += 1;
+                }
+                None => {}
+            }
+            self.expiring_listings.remove(&index);
+        }
+
+        PruneResult {
+            removed,
+            next: if end < self.next_expiring_listing {
+                Some(end)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Adds `amount` to the proceeds owed to `account`.
+    pub fn credit_proceeds(&mut self, account: &Address, amount: Amount) {
+        if amount == Amount::zero() {
+            return;
+        }
+        let mut balance =
+            self.proceeds.entry(canonical_address(account)).or_insert(Amount::zero());
+        *balance += amount;
+        self.total_proceeds += amount;
+    }
+
+    /// Removes and returns the proceeds owed to `account`.
+    pub fn take_proceeds(&mut self, account: &Address) -> Amount {
+        let amount = self
+            .proceeds
+            .remove_and_get(&canonical_address(account))
+            .unwrap_or(Amount::zero());
+        self.total_proceeds -= amount;
+        amount
+    }
+
+    /// Gets the proceeds owed to `account`.
+    pub fn proceeds_of(&self, account: &Address) -> Amount {
+        self.proceeds.get(&canonical_address(account)).map_or(Amount::zero(), |a| *a)
+    }
+
+    /// Gets the remaining buyback capacity at time `now` for a token sold by
+    /// `seller`.
+    pub fn buyback_capacity(
+        &self,
+        token_info: &TokenInfo<T>,
+        seller: &Address,
+        now: Timestamp,
+    ) -> BuybackCapacity {
+        let day = day_of(now);
+        let limits = self.buyback_limits;
+        let token_spent = self
+            .buyback_spent_per_token
+            .get(token_info)
+            .map_or(Amount::zero(), |s| s.spent_on(day));
+        let account_spent = self
+            .buyback_spent_per_account
+            .get(&canonical_address(seller))
+            .map_or(Amount::zero(), |s| s.spent_on(day));
+
+        let remaining =
+            |limit: Amount, spent: Amount| limit.checked_sub(spent).unwrap_or(Amount::zero());
+        let per_token = remaining(limits.per_token, token_spent);
+        let per_account = remaining(limits.per_account, account_spent);
+        let per_day = remaining(limits.per_day, self.buyback_spent.spent_on(day));
+
+        BuybackCapacity {
+            reserve: self.reserve,
+            per_token,
+            per_account,
+            per_day,
+            available: self.reserve.min(per_token).min(per_account).min(per_day),
+        }
+    }
+
+    /// Takes `amount` out of the reserve for a buyback of a token sold by
+    /// `seller`, and counts it against the daily limits. The caller must
+    /// check `amount` against [`State::buyback_capacity`] first.
+    pub fn record_buyback(
+        &mut self,
+        token_info: &TokenInfo<T>,
+        seller: &Address,
+        amount: Amount,
+        now: Timestamp,
+    ) {
+        let day = day_of(now);
+        let no_spend = DailySpend {
+            day,
+            spent: Amount::zero(),
+        };
+        self.reserve -= amount;
+        self.buyback_spent.record(day, amount);
+        self.buyback_spent_per_token
+            .entry(token_info.clone())
+            .or_insert(no_spend)
+            .record(day, amount);
+        self.buyback_spent_per_account
+            .entry(canonical_address(seller))
+            .or_insert(no_spend)
+            .record(day, amount);
+    }
+
+    /// Whether tokens of the CIS2 contract at `address` may be traded.
+    pub fn is_collection_allowed(&self, address: &ContractAddress) -> bool {
+        self.permissionless || self.collections.get(address).is_some()
+    }
+
+    /// Gets the royalty of a collection at time `now`.
+    pub fn royalty_of(&self, address: &ContractAddress, now: Timestamp) -> Option<Royalty> {
+        match self.royalty_changes.get(address) {
+            Some(change) if change.effective_at <= now => Some(change.royalty),
+            _ => self.royalties.get(address).map(|r| *r),
+        }
+    }
+
+    /// Sets or removes the royalty of a collection at time `now`. A royalty
+    /// taking a larger share of sales than the current one only applies from
+    /// `effective_at`, replacing any raise still waiting; other changes apply
+    /// at once.
+    pub fn set_royalty(
+        &mut self,
+        address: ContractAddress,
+        royalty: Option<Royalty>,
+        now: Timestamp,
+        effective_at: Timestamp,
+    ) {
+        let current = self.royalty_of(&address, now);
+        // Settles an earlier raise which has applied by now.
+        self.royalty_changes.remove(&address);
+        match current {
+            Some(current) => {
+                let _ = self.royalties.insert(address, current);
+            }
+            None => self.royalties.remove(&address),
+        }
+
+        match royalty {
+            Some(royalty) if royalty.basis_points > current.map_or(0, |r| r.basis_points) => {
+                let _ = self.royalty_changes.insert(address, RoyaltyChange {
+                    royalty,
+                    effective_at,
+                });
+            }
+            Some(royalty) => {
+                let _ = self.royalties.insert(address, royalty);
+            }
+            None => self.royalties.remove(&address),
+        }
+    }
+
+    /// Gets the raise of the royalty of a collection which has not applied yet
+    /// at time `now`, if any.
+    pub fn royalty_change_of(
+        &self,
+        address: &ContractAddress,
+        now: Timestamp,
+    ) -> Option<RoyaltyChange> {
+        self.royalty_changes
+            .get(address)
+            .map(|c| *c)
+            .filter(|change| change.effective_at > now)
+    }
+
+    /// The identity policies a trader in a market has to meet: the one of the
+    /// marketplace, the one of the collection and the one of the token.
+    pub fn identity_policies_of(&self, market: &TokenInfo<T>) -> Vec<IdentityPolicy> {
+        self.identity_policy
+            .iter()
+            .cloned()
+            .chain(self.collection_policies.get(&market.address).map(|p| p.clone()))
+            .chain(self.token_policies.get(market).map(|p| p.clone()))
+            .collect()
+    }
+
+    /// Whether signed identity claims of `verifier` are trusted.
+    pub fn is_verifier(&self, verifier: &AccountAddress) -> bool {
+        self.verifiers.contains(&canonical(verifier))
+    }
+
+    /// Checks trusted and unexpired claims against the identity policies a
+    /// trader in a market has to meet.
+    pub fn check_claims(
+        &self,
+        market: &TokenInfo<T>,
+        verifier: &AccountAddress,
+        claims: &IdentityClaims,
+        now: Timestamp,
+    ) -> Result<(), IneligibleReason> {
+        if !self.is_verifier(verifier) {
+            return Err(IneligibleReason::UnknownVerifier);
+        }
+        if claims.expires_at <= now {
+            return Err(IneligibleReason::Expired);
+        }
+        self.identity_policies_of(market)
+            .iter()
+            .try_for_each(|policy| policy.check(claims))
+    }
+
+    /// Checks the claims in the attestation registry about `trader` against
+    /// the identity policies a trader in a market has to meet.
+    pub fn check_eligibility(
+        &self,
+        market: &TokenInfo<T>,
+        trader: &Address,
+        now: Timestamp,
+    ) -> Result<(), IneligibleReason> {
+        if self.identity_policies_of(market).is_empty() {
+            return Ok(());
+        }
+        let record = self
+            .attestations
+            .get(&canonical_address(trader))
+            .ok_or(IneligibleReason::NotAttested)?;
+        if record.revoked {
+            return Err(IneligibleReason::Revoked);
+        }
+        self.check_claims(market, &record.verifier, &record.claims, now)
+    }
+
+    /// Whether trading in a market is halted at time `now`.
+    pub fn is_halted(&self, market: &TokenInfo<T>, now: Timestamp) -> bool {
+        self.breakers
+            .get(market)
+            .and_then(|breaker| breaker.halted_until)
+            .is_some_and(|halted_until| now < halted_until)
+    }
+
+    /// Records the CCD unit price of a trade in a market. If the price moved
+    /// too far from the reference price, trading in the market is halted and
+    /// the breaker as it was before the trade is returned, with the end of the
+    /// halt.
+    pub fn record_trade_price(
+        &mut self,
+        market: &TokenInfo<T>,
+        price: Amount,
+        now: Timestamp,
+    ) -> Option<MarketBreaker> {
+        let config = self.circuit_breaker?;
+        let fresh = MarketBreaker {
+            reference_price: price,
+            reference_time: now,
+            halted_until: None,
+        };
+        let mut breaker = self.breakers.entry(market.clone()).or_insert(fresh);
+
+        let in_window = now
+            .duration_since(breaker.reference_time)
+            .is_some_and(|elapsed| elapsed <= config.window);
+        if !in_window {
+            *breaker = fresh;
+            return None;
+        }
+        let max_move = u128::from(config.max_move_basis_points);
+        if price_move_basis_points(breaker.reference_price, price) <= max_move {
+            return None;
+        }
+
+        let halted_until = Timestamp::from_timestamp_millis(
+            now.timestamp_millis()
+                .saturating_add(config.cooldown.millis()),
+        );
+        let tripped = MarketBreaker {
+            halted_until: Some(halted_until),
+            ..*breaker
+        };
+        *breaker = MarketBreaker {
+            halted_until: Some(halted_until),
+            ..fresh
+        };
+        Some(tripped)
+    }
+
+    /// Adds the CCD unit price of a trade to the price history of a market.
+    pub fn observe_price(&mut self, market: &TokenInfo<T>, price: Amount, now: Timestamp) {
+        let mut observations =
+            self.price_observations.entry(market.clone()).or_default();
+        let cumulative = match observations.last_mut() {
+            Some(last) if last.time == now => {
+                last.price = price;
+                return;
+            }
+            Some(last) => last.cumulative_at(now),
+            None => 0,
+        };
+        if observations.len() == MAX_PRICE_OBSERVATIONS {
+            observations.remove(0);
+        }
+        observations.push(PriceObservation {
+            time: now,
+            price,
+            cumulative,
+        });
+    }
+
+    /// The time-weighted average CCD unit price of a market over the `window`
+    /// before `now`. Returns `None` if the kept history does not cover the
+    /// window.
+    pub fn consult(
+        &self,
+        market: &TokenInfo<T>,
+        window: Duration,
+        now: Timestamp,
+    ) -> Option<Amount> {
+        let start = now.checked_sub(window)?;
+        let observations = self.price_observations.get(market)?;
+        let first = observations.iter().rev().find(|o| o.time <= start)?;
+        let last = observations.last()?;
+        let average = (last.cumulative_at(now) - first.cumulative_at(start))
+            / u128::from(window.millis().max(1));
+        u64::try_from(average).ok().map(Amount::from_micro_ccd)
+    }
+
+    /// Whether `address`, under any alias, is on the blocklist.
+    pub fn is_blocked(&self, address: &Address) -> bool {
+        self.blocked.contains(&canonical_address(address))
+    }
+
+    /// The lowest nonce of the orders of `maker` which are not cancelled.
+    pub fn min_nonce_of(&self, maker: &AccountAddress) -> u64 {
+        self.min_nonces.get(&canonical(maker)).map_or(0, |n| *n)
+    }
+
+    /// Quantity bought so far through the order with the given hash.
+    pub fn filled_of(&self, order: &HashSha2256) -> Option<A> {
+        self.order_fills.get(order).map(|f| *f)
+    }
+
+    /// Credits a referral reward of `amount` to `referrer`.
+    pub fn credit_referral(&mut self, referrer: &AccountAddress, amount: Amount) {
+        self.credit_proceeds(&Address::Account(*referrer), amount);
+        let mut stats = self.referrals.entry(canonical(referrer)).or_insert(ReferralStats {
+            trades: 0,
+            earned: Amount::zero(),
+        });
+        stats.trades += 1;
+        stats.earned += amount;
+    }
+
+    /// Gets the referral rewards earned by `referrer`.
+    pub fn referral_stats_of(&self, referrer: &AccountAddress) -> ReferralStats {
+        self.referrals.get(&canonical(referrer)).map_or(
+            ReferralStats {
+                trades: 0,
+                earned: Amount::zero(),
+            },
+            |r| *r,
+        )
+    }
+
+    /// Applies a change to the marketplace parameters.
+    pub fn apply_action(&mut self, action: DexAction) {
+        match action {
+            DexAction::SetFees(fees) => self.fees = fees,
+            DexAction::RegisterCollection(address, info) => {
+                let _ = self.collections.insert(address, info);
+            }
+            DexAction::RemoveCollection(address) => self.collections.remove(&address),
+            DexAction::SetPermissionless(permissionless) => self.permissionless = permissionless,
+            DexAction::SetPaused(paused) => self.paused = paused,
+            DexAction::SetContractPrincipals(allowed) => self.allow_contract_principals = allowed,
+            DexAction::SetIdentityPolicy(policy) => self.identity_policy = policy,
+            DexAction::SetVerifier(verifier, true) => {
+                self.verifiers.insert(canonical(&verifier));
+            }
+            DexAction::SetVerifier(verifier, false) => {
+                self.verifiers.remove(&canonical(&verifier));
+            }
+            DexAction::SetCircuitBreaker(config) => self.circuit_breaker = config,
+        }
+    }
+
+    /// Stores a new proposal and returns its id.
+    pub fn create_proposal(&mut self, proposal: Proposal<A>) -> u64 {
+        let proposal_id = self.next_proposal_id;
+        self.next_proposal_id += 1;
+        let _ = self.proposals.insert(proposal_id, proposal);
+        proposal_id
+    }
+
+    /// Gets a proposal by its id.
+    pub fn get_proposal(&self, proposal_id: u64) -> Option<Proposal<A>> {
+        self.proposals.get(&proposal_id).map(|p| p.clone())
+    }
+
+    /// Stores a new admin proposal and returns its id.
+    pub fn create_admin_proposal(&mut self, proposal: AdminProposal<T, A>) -> u64 {
+        let proposal_id = self.next_admin_proposal_id;
+        self.next_admin_proposal_id += 1;
+        let _ = self.admin_proposals.insert(proposal_id, proposal);
+        proposal_id
+    }
+
+    /// Gets an admin proposal by its id.
+    pub fn get_admin_proposal(&self, proposal_id: u64) -> Option<AdminProposal<T, A>> {
+        self.admin_proposals.get(&proposal_id).map(|p| p.clone())
+    }
+
+    /// Adds an admin action to the queue and returns its id.
+    pub fn queue_admin_action(&mut self, queued: QueuedAdminAction<T, A>) -> u64 {
+        let queue_id = self.next_queued_admin_action_id;
+        self.next_queued_admin_action_id += 1;
+        let _ = self.admin_queue.insert(queue_id, queued);
+        queue_id
+    }
+
+    /// Gets a queued admin action by its id.
+    pub fn get_queued_admin_action(&self, queue_id: u64) -> Option<QueuedAdminAction<T, A>> {
+        self.admin_queue.get(&queue_id).map(|q| q.clone())
+    }
+
+    /// Returns all queued admin actions with their ids.
+    pub fn queued_admin_actions(&self) -> Vec<(u64, QueuedAdminAction<T, A>)> {
+        self.admin_queue.iter().map(|(id, q)| (*id, q.clone())).collect()
+    }
+
+    /// Stores a new auction and returns its id.
+    pub fn create_auction(&mut self, auction: AuctionState<T, A>) -> u64 {
+        let auction_id = self.next_auction_id;
+        self.next_auction_id += 1;
+        let _ = self.auctions.insert(auction_id, auction);
+        auction_id
+    }
+
+    /// Gets an auction by its id.
+    pub fn get_auction(&self, auction_id: u64) -> Option<AuctionState<T, A>> {
+        self.auctions.get(&auction_id).map(|a| a.clone())
+    }
+
+    /// Removes an auction once it is settled.
+    pub fn remove_auction(&mut self, auction_id: u64) {
+        self.auctions.remove(&auction_id);
+    }
+}
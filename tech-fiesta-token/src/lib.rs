@@ -1,6 +1,5 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
-pub mod techFiestaToken {
 use concordium_cis2::*;
 use concordium_std::{collections::BTreeMap, EntrypointName, *};
 
@@ -109,10 +108,68 @@ pub struct MintParams {
 
 
 #[derive(Debug, Serialize, SchemaType)]
-struct SetImplementorsParams {
-    id:           StandardIdentifierOwned,
+pub struct SetImplementorsParams {
+    pub id:           StandardIdentifierOwned,
     /// The addresses of the implementors of the standard.
-    implementors: Vec<ContractAddress>,
+    pub implementors: Vec<ContractAddress>,
+}
+
+/// An M-of-N set of accounts controlling the privileged entrypoints.
+#[derive(Debug, Serialize, SchemaType)]
+pub struct MultisigConfig {
+    #[concordium(size_length = 1)]
+    pub signers:   Vec<AccountAddress>,
+    /// Number of signers which must approve an admin action.
+    pub threshold: u8,
+}
+
+impl MultisigConfig {
+    fn is_valid(&self) -> bool {
+        let distinct = self
+            .signers
+            .iter()
+            .enumerate()
+            .all(|(i, signer)| !self.signers[..i].iter().any(|s| s.is_alias(signer)));
+        self.threshold > 0 && usize::from(self.threshold) <= self.signers.len() && distinct
+    }
+
+    fn is_signer(&self, account: &AccountAddress) -> bool {
+        self.signers.iter().any(|signer| signer.is_alias(account))
+    }
+
+    /// Whether enough of the current signers are among `approvals`.
+    fn is_approved(&self, approvals: &[AccountAddress]) -> bool {
+        approvals.iter().filter(|a| self.is_signer(a)).count() >= usize::from(self.threshold)
+    }
+}
+
+#[derive(Serialize, SchemaType)]
+pub struct UpgradeParams {
+    pub module:  ModuleReference,
+    /// Entrypoint of the new module to call after the upgrade, with its
+    /// parameter.
+    pub migrate: Option<(OwnedEntrypointName, OwnedParameter)>,
+}
+
+/// A privileged action, run once approved by the multisig signers.
+///
+/// Unlike the admin queue of the `RagnarDEX`, there is no delay: the action
+/// runs within the approval which reaches the threshold.
+#[derive(Serialize, SchemaType)]
+pub enum AdminAction {
+    Mint(MintParams),
+    SetImplementors(SetImplementorsParams),
+    SetAdmins(MultisigConfig),
+    Upgrade(UpgradeParams),
+    SetBlocklist(Option<ContractAddress>),
+}
+
+/// An admin action waiting for approvals.
+#[derive(Serialize, SchemaType)]
+struct AdminProposal {
+    action:    AdminAction,
+    #[concordium(size_length = 1)]
+    approvals: Vec<AccountAddress>,
 }
 
 
@@ -120,17 +177,66 @@ struct SetImplementorsParams {
 #[derive(Serial, DeserialWithState, Deletable)]
 #[concordium(state_parameter = "S")]
 pub struct AddressState<S = StateApi> {
-    balances:  StateMap<ContractTokenId, ContractTokenAmount, S>,
-    operators: StateSet<Address, S>,
+    balances:          StateMap<ContractTokenId, ContractTokenAmount, S>,
+    operators:         StateSet<Address, S>,
+    /// The balance after every change, with the time of the change, keyed by
+    /// the token and the number of the change. Used to look up past balances
+    /// for governance snapshots. Each entry is stored on its own, so that
+    /// dust transfers do not make the state of the address costly to load.
+    checkpoints:       StateMap<(ContractTokenId, u32), (Timestamp, ContractTokenAmount), S>,
+    /// Number of checkpoints of each token.
+    checkpoint_counts: StateMap<ContractTokenId, u32, S>,
 }
 
 impl AddressState {
     fn empty(state_builder: &mut StateBuilder) -> Self {
         AddressState {
-            balances:  state_builder.new_map(),
-            operators: state_builder.new_set(),
+            balances:          state_builder.new_map(),
+            operators:         state_builder.new_set(),
+            checkpoints:       state_builder.new_map(),
+            checkpoint_counts: state_builder.new_map(),
         }
     }
+
+    /// Records the balance of a token after a change at time `now`. Several
+    /// changes within the same block keep only the last balance.
+    fn checkpoint(
+        &mut self,
+        token_id: &ContractTokenId,
+        balance: ContractTokenAmount,
+        now: Timestamp,
+    ) {
+        let count = self.checkpoint_counts.get(token_id).map_or(0, |count| *count);
+        if let Some(last) = count.checked_sub(1) {
+            if let Some(mut checkpoint) = self.checkpoints.get_mut(&(*token_id, last)) {
+                if checkpoint.0 == now {
+                    checkpoint.1 = balance;
+                    return;
+                }
+            }
+        }
+        let _ = self.checkpoints.insert((*token_id, count), (now, balance));
+        let _ = self.checkpoint_counts.insert(*token_id, count + 1);
+    }
+
+    /// The balance of a token after the last change before `time`, found by
+    /// binary search over the checkpoints.
+    fn balance_before(&self, token_id: &ContractTokenId, time: Timestamp) -> ContractTokenAmount {
+        let time_of = |index| self.checkpoints.get(&(*token_id, index)).map(|c| c.0);
+        // Checkpoints below `low` are before `time`, those from `high` on are not.
+        let mut low = 0;
+        let mut high = self.checkpoint_counts.get(token_id).map_or(0, |count| *count);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match time_of(mid) {
+                Some(at) if at < time => low = mid + 1,
+                _ => high = mid,
+            }
+        }
+        low.checked_sub(1)
+            .and_then(|last| self.checkpoints.get(&(*token_id, last)).map(|c| c.1))
+            .unwrap_or(0.into())
+    }
 }
 
 #[derive(Serial, DeserialWithState)]
@@ -145,9 +251,14 @@ pub struct State<S = StateApi> {
     /// A map with contract addresses providing implementations of additional
     /// standards.
     implementors:       StateMap<StandardIdentifierOwned, Vec<ContractAddress>, S>,
-    
-    
-    
+    /// Once set, minting, setting implementors and upgrades need the approval
+    /// of these signers.
+    admins:             Option<MultisigConfig>,
+    admin_proposals:    StateMap<u64, AdminProposal, S>,
+    next_admin_proposal_id: u64,
+    /// The `RagnarDEX` instance whose blocklist is enforced on transfers and
+    /// mints.
+    blocklist:          Option<ContractAddress>,
 }
 
 /// The different errors the contract can produce.
@@ -170,13 +281,39 @@ pub enum CustomContractError {
     
     /// Max supply reached
     MaxSupplyReached, // -22
-    
+    /// No multisig admins are set.
+    MultisigNotConfigured,
+    /// No admin action with the given id is waiting for approvals.
+    AdminActionNotFound,
+    /// The signer already approved the admin action.
+    AlreadyApproved,
+    /// The threshold is zero, above the number of signers, or a signer is
+    /// listed twice.
+    InvalidAdmins,
+    /// Upgrading the module or calling its migration entrypoint failed.
+    FailedUpgrade,
+    /// The sender or receiver is on the blocklist.
+    Blocked,
 }
 
 pub type ContractError = Cis2Error<CustomContractError>;
 
 pub type ContractResult<A> = Result<A, ContractError>;
 
+/// The address balances and operators are kept under. All aliases of an
+/// account share the alias whose last three bytes are zero.
+fn canonical(address: &Address) -> Address {
+    match address {
+        Address::Account(account) => Address::Account(account.get_alias_unchecked(0)),
+        Address::Contract(contract) => Address::Contract(*contract),
+    }
+}
+
+/// Whether `address` is `account`, or another alias of it.
+fn is_alias_of(address: &Address, account: &AccountAddress) -> bool {
+    matches!(address, Address::Account(a) if a.is_alias(account))
+}
+
 
 
 
@@ -206,7 +343,10 @@ impl State {
             max_supply: state_builder.new_map(),
             token_balance: state_builder.new_map(),
             implementors: state_builder.new_map(),
-            
+            admins: None,
+            admin_proposals: state_builder.new_map(),
+            next_admin_proposal_id: 0,
+            blocklist: None,
         }
     }
 
@@ -216,6 +356,7 @@ impl State {
         metadata_url: &MetadataUrl,
         amount: ContractTokenAmount,
         owner: &Address,
+        now: Timestamp,
         state_builder: &mut StateBuilder,
     ) {
         let token_metadata = self.tokens.get(token_id).map(|x| x.to_owned());
@@ -224,10 +365,14 @@ impl State {
         }
         let mut owner_state = self
             .state
-            .entry(*owner)
+            .entry(canonical(owner))
             .or_insert_with(|| AddressState::empty(state_builder));
-        let mut owner_balance = owner_state.balances.entry(*token_id).or_insert(0.into());
-        *owner_balance += amount;
+        let owner_balance = {
+            let mut owner_balance = owner_state.balances.entry(*token_id).or_insert(0.into());
+            *owner_balance += amount;
+            *owner_balance
+        };
+        owner_state.checkpoint(token_id, owner_balance, now);
     
         let mut circulating_supply = self.token_balance.entry(*token_id).or_insert(0.into());
         *circulating_supply += amount;
@@ -247,12 +392,27 @@ impl State {
         address: &Address,
     ) -> ContractResult<ContractTokenAmount> {
         ensure!(self.contains_token(token_id), ContractError::InvalidTokenId);
-        let balance = self.state.get(address).map_or(0.into(), |address_state| {
+        let balance = self.state.get(&canonical(address)).map_or(0.into(), |address_state| {
             address_state.balances.get(token_id).map_or(0.into(), |x| *x)
         });
         Ok(balance)
     }
 
+    /// The balance of an address just before `time`, that is after all
+    /// changes made in blocks before `time`.
+    fn balance_at(
+        &self,
+        token_id: &ContractTokenId,
+        address: &Address,
+        time: Timestamp,
+    ) -> ContractResult<ContractTokenAmount> {
+        ensure!(self.contains_token(token_id), ContractError::InvalidTokenId);
+        let balance = self.state.get(&canonical(address)).map_or(0.into(), |address_state| {
+            address_state.balance_before(token_id, time)
+        });
+        Ok(balance)
+    }
+
     #[inline(always)]
     fn set_max_supply(&mut self, token_id: &ContractTokenId, max_supply: ContractTokenAmount) {
         let _ = self.max_supply.insert(*token_id, max_supply);
@@ -262,7 +422,7 @@ impl State {
     #[inline(always)]
     fn get_token_supply(&self, token_id: &ContractTokenId) -> ContractResult<ContractTokenAmount> {
         ensure!(
-            self.contains_token(&token_id),
+            self.contains_token(token_id),
             ContractError::InvalidTokenId
         );
         let supply = self.max_supply.get(token_id).map_or(0.into(), |x| *x);
@@ -283,8 +443,8 @@ impl State {
     /// Check if an address is an operator of a given owner address.
     fn is_operator(&self, address: &Address, owner: &Address) -> bool {
         self.state
-            .get(owner)
-            .map(|address_state| address_state.operators.contains(address))
+            .get(&canonical(owner))
+            .map(|address_state| address_state.operators.contains(&canonical(address)))
             .unwrap_or(false)
     }
 
@@ -294,6 +454,7 @@ impl State {
         amount: ContractTokenAmount,
         from: &Address,
         to: &Address,
+        now: Timestamp,
         state_builder: &mut StateBuilder,
     ) -> ContractResult<()> {
         ensure!(self.contains_token(token_id), ContractError::InvalidTokenId);
@@ -303,19 +464,28 @@ impl State {
 
         {
             let mut from_address_state =
-                self.state.entry(*from).occupied_or(ContractError::InsufficientFunds)?;
-            let mut from_balance = from_address_state
-                .balances
-                .entry(*token_id)
-                .occupied_or(ContractError::InsufficientFunds)?;
-            ensure!(*from_balance >= amount, ContractError::InsufficientFunds);
-            *from_balance -= amount;
+                self.state.entry(canonical(from)).occupied_or(ContractError::InsufficientFunds)?;
+            let from_balance = {
+                let mut from_balance = from_address_state
+                    .balances
+                    .entry(*token_id)
+                    .occupied_or(ContractError::InsufficientFunds)?;
+                ensure!(*from_balance >= amount, ContractError::InsufficientFunds);
+                *from_balance -= amount;
+                *from_balance
+            };
+            from_address_state.checkpoint(token_id, from_balance, now);
         }
 
         let mut to_address_state =
-            self.state.entry(*to).or_insert_with(|| AddressState::empty(state_builder));
-        let mut to_address_balance = to_address_state.balances.entry(*token_id).or_insert(0.into());
-        *to_address_balance += amount;
+            self.state.entry(canonical(to)).or_insert_with(|| AddressState::empty(state_builder));
+        let to_address_balance = {
+            let mut to_address_balance =
+                to_address_state.balances.entry(*token_id).or_insert(0.into());
+            *to_address_balance += amount;
+            *to_address_balance
+        };
+        to_address_state.checkpoint(token_id, to_address_balance, now);
 
         Ok(())
     }
@@ -326,14 +496,16 @@ impl State {
         operator: &Address,
         state_builder: &mut StateBuilder,
     ) {
-        let mut owner_state =
-            self.state.entry(*owner).or_insert_with(|| AddressState::empty(state_builder));
-        owner_state.operators.insert(*operator);
+        let mut owner_state = self
+            .state
+            .entry(canonical(owner))
+            .or_insert_with(|| AddressState::empty(state_builder));
+        owner_state.operators.insert(canonical(operator));
     }
 
     fn remove_operator(&mut self, owner: &Address, operator: &Address) {
-        self.state.entry(*owner).and_modify(|address_state| {
-            address_state.operators.remove(operator);
+        self.state.entry(canonical(owner)).and_modify(|address_state| {
+            address_state.operators.remove(&canonical(operator));
         });
     }
 
@@ -376,6 +548,7 @@ fn contract_init(
     let mut state = State::empty(state_builder);
 
     let invoker = Address::Account(ctx.init_origin());
+    let now = ctx.metadata().slot_time();
 
     // Preminting of tokens
     for (token_id, token_info) in params.premint_tokens {
@@ -386,6 +559,7 @@ fn contract_init(
             &token_info.0,
             token_info.1.amount,
             &invoker,
+            now,
             state_builder,
         );
 
@@ -477,12 +651,15 @@ fn contract_view(_ctx: &ReceiveContext, host: &Host<State>) -> ReceiveResult<Vie
 
 fn mint(
     params: MintParams,
+    now: Timestamp,
     host: &mut Host<State>,
     logger: &mut impl HasLogger,
 ) -> ContractResult<()> {
 
     
 
+    ensure_not_blocked(host, vec![params.owner])?;
+
     let (state, builder) = host.state_and_builder();
     for (token_id, token_info) in params.tokens {
         if !state.contains_token(&token_id) {
@@ -497,7 +674,7 @@ fn mint(
             );
 
             ensure!(
-                &token_info.1.amount <= &(max_supply - circulating_supply),
+                token_info.1.amount <= max_supply - circulating_supply,
                 ContractError::Custom(CustomContractError::MaxSupplyReached)
             );
         }
@@ -507,6 +684,7 @@ fn mint(
             &token_info.0,
             token_info.1.amount,
             &params.owner,
+            now,
             builder,
         );
 
@@ -544,12 +722,15 @@ fn contract_mint(
     let sender = ctx.sender();
 
     
-    ensure!(sender.matches_account(&owner), ContractError::Unauthorized);
+    ensure!(
+        is_alias_of(&sender, &owner) && host.state().admins.is_none(),
+        ContractError::Unauthorized
+    );
     
     
     let params: MintParams = ctx.parameter_cursor().get()?;
 
-    mint(params, host, logger)?;
+    mint(params, ctx.metadata().slot_time(), host, logger)?;
 
     Ok(())
 }
@@ -561,6 +742,7 @@ type TransferParameter = TransferParams<ContractTokenId, ContractTokenAmount>;
 
 fn transfer(
     transfer: concordium_cis2::Transfer<ContractTokenId, ContractTokenAmount>,
+    now: Timestamp,
     host: &mut Host<State>,
     logger: &mut impl HasLogger,
 ) -> ContractResult<()> {
@@ -570,7 +752,7 @@ fn transfer(
 
     let (state, builder) = host.state_and_builder();
 
-    state.transfer(&transfer.token_id, transfer.amount, &transfer.from, &to_address, builder)?;
+    state.transfer(&transfer.token_id, transfer.amount, &transfer.from, &to_address, now, builder)?;
 
     logger.log(&Cis2Event::Transfer(TransferEvent {
         token_id: transfer.token_id,
@@ -607,15 +789,17 @@ fn contract_transfer(
 ) -> ContractResult<()> {
     let TransferParams(transfers): TransferParameter = ctx.parameter_cursor().get()?;
     let sender = ctx.sender();
+    let now = ctx.metadata().slot_time();
 
     for transfer_entry in transfers {
         ensure!(
-            transfer_entry.from == sender
+            canonical(&transfer_entry.from) == canonical(&sender)
                 || host.state().is_operator(&sender, &transfer_entry.from),
             ContractError::Unauthorized
         );
+        ensure_not_blocked(host, vec![sender, transfer_entry.from, transfer_entry.to.address()])?;
 
-        transfer(transfer_entry, host, logger)?;
+        transfer(transfer_entry, now, host, logger)?;
     }
     Ok(())
 }
@@ -695,6 +879,43 @@ fn contract_balance_of(
     Ok(result)
 }
 
+/// A query for the balance of an address just before `time`.
+#[derive(Serialize, SchemaType)]
+pub struct BalanceOfAtQuery {
+    pub token_id: ContractTokenId,
+    pub address:  Address,
+    pub time:     Timestamp,
+}
+
+#[derive(Serialize, SchemaType)]
+pub struct BalanceOfAtQueryParams {
+    #[concordium(size_length = 2)]
+    pub queries: Vec<BalanceOfAtQuery>,
+}
+
+/// Get the balances of addresses at past times, for voting snapshots. Takes
+/// into account all changes made in blocks before the given time.
+#[receive(
+    contract = "techFiestaToken",
+    name = "balanceOfAt",
+    parameter = "BalanceOfAtQueryParams",
+    return_value = "ContractBalanceOfQueryResponse",
+    error = "ContractError"
+)]
+fn contract_balance_of_at(
+    ctx: &ReceiveContext,
+    host: &Host<State>,
+) -> ContractResult<ContractBalanceOfQueryResponse> {
+    let params: BalanceOfAtQueryParams = ctx.parameter_cursor().get()?;
+    let mut response = Vec::with_capacity(params.queries.len());
+    for query in params.queries {
+        let amount = host.state().balance_at(&query.token_id, &query.address, query.time)?;
+        response.push(amount);
+    }
+    let result = ContractBalanceOfQueryResponse::from(response);
+    Ok(result)
+}
+
 #[receive(
     contract = "techFiestaToken",
     name = "operatorOf",
@@ -779,9 +1000,195 @@ fn contract_supports(
     mutable
 )]
 fn contract_set_implementor(ctx: &ReceiveContext, host: &mut Host<State>) -> ContractResult<()> {
-    ensure!(ctx.sender().matches_account(&ctx.owner()), ContractError::Unauthorized);
+    ensure!(
+        is_alias_of(&ctx.sender(), &ctx.owner()) && host.state().admins.is_none(),
+        ContractError::Unauthorized
+    );
     let params: SetImplementorsParams = ctx.parameter_cursor().get()?;
     host.state_mut().set_implementors(params.id, params.implementors);
     Ok(())
 }
-}
\ No newline at end of file
+
+/// Sets the `RagnarDEX` instance whose blocklist is enforced, or stops
+/// enforcing one. Owner only, until multisig admins are set.
+#[receive(
+    contract = "techFiestaToken",
+    name = "setBlocklist",
+    parameter = "Option<ContractAddress>",
+    error = "ContractError",
+    mutable
+)]
+fn contract_set_blocklist(ctx: &ReceiveContext, host: &mut Host<State>) -> ContractResult<()> {
+    ensure!(
+        is_alias_of(&ctx.sender(), &ctx.owner()) && host.state().admins.is_none(),
+        ContractError::Unauthorized
+    );
+    host.state_mut().blocklist = ctx.parameter_cursor().get()?;
+    Ok(())
+}
+
+/// Returns `Blocked` if any of the addresses is on the blocklist, by calling
+/// `isBlocked` of the `RagnarDEX` instance.
+fn ensure_not_blocked(host: &Host<State>, addresses: Vec<Address>) -> ContractResult<()> {
+    let blocklist = match host.state().blocklist {
+        Some(blocklist) => blocklist,
+        None => return Ok(()),
+    };
+    let mut response = host
+        .invoke_contract_read_only(
+            &blocklist,
+            &addresses,
+            EntrypointName::new_unchecked("isBlocked"),
+            Amount::zero(),
+        )
+        .map_err(|_| CustomContractError::InvokeContractError)?
+        .ok_or(CustomContractError::InvokeContractError)?;
+    let blocked: bool = response.get().map_err(|_| CustomContractError::InvokeContractError)?;
+    ensure!(!blocked, ContractError::Custom(CustomContractError::Blocked));
+    Ok(())
+}
+
+/// Hands minting, setting implementors and upgrades over to an M-of-N set of
+/// signers. Owner only, until multisig admins are set. Afterwards the signers
+/// change with a `SetAdmins` admin action.
+#[receive(
+    contract = "techFiestaToken",
+    name = "setAdmins",
+    parameter = "MultisigConfig",
+    error = "ContractError",
+    mutable
+)]
+fn contract_set_admins(ctx: &ReceiveContext, host: &mut Host<State>) -> ContractResult<()> {
+    ensure!(
+        is_alias_of(&ctx.sender(), &ctx.owner()) && host.state().admins.is_none(),
+        ContractError::Unauthorized
+    );
+    let admins: MultisigConfig = ctx.parameter_cursor().get()?;
+    ensure!(admins.is_valid(), ContractError::Custom(CustomContractError::InvalidAdmins));
+    host.state_mut().admins = Some(admins);
+    Ok(())
+}
+
+/// Returns the sender if it is one of the multisig signers.
+fn ensure_signer(ctx: &ReceiveContext, state: &State) -> ContractResult<AccountAddress> {
+    let admins = state
+        .admins
+        .as_ref()
+        .ok_or(ContractError::Custom(CustomContractError::MultisigNotConfigured))?;
+    match ctx.sender() {
+        Address::Account(account) if admins.is_signer(&account) => Ok(account),
+        _ => bail!(ContractError::Unauthorized),
+    }
+}
+
+/// Records the approval of `signer` for an admin proposal, and runs the action
+/// once the threshold is reached.
+fn add_approval(
+    ctx: &ReceiveContext,
+    proposal_id: u64,
+    signer: AccountAddress,
+    host: &mut Host<State>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let approved = {
+        let state = host.state_mut();
+        let mut proposal = state
+            .admin_proposals
+            .entry(proposal_id)
+            .occupied_or(ContractError::Custom(CustomContractError::AdminActionNotFound))?;
+        ensure!(
+            !proposal.approvals.iter().any(|a| a.is_alias(&signer)),
+            ContractError::Custom(CustomContractError::AlreadyApproved)
+        );
+        proposal.approvals.push(signer);
+        let approvals = proposal.approvals.clone();
+        drop(proposal);
+        state.admins.as_ref().is_some_and(|admins| admins.is_approved(&approvals))
+    };
+    if !approved {
+        return Ok(());
+    }
+
+    let proposal = host
+        .state_mut()
+        .admin_proposals
+        .remove_and_get(&proposal_id)
+        .ok_or(ContractError::Custom(CustomContractError::AdminActionNotFound))?;
+
+    match proposal.action {
+        AdminAction::Mint(params) => mint(params, ctx.metadata().slot_time(), host, logger)?,
+        AdminAction::SetImplementors(params) => {
+            host.state_mut().set_implementors(params.id, params.implementors)
+        }
+        AdminAction::SetAdmins(admins) => {
+            ensure!(admins.is_valid(), ContractError::Custom(CustomContractError::InvalidAdmins));
+            host.state_mut().admins = Some(admins);
+        }
+        AdminAction::SetBlocklist(blocklist) => host.state_mut().blocklist = blocklist,
+        AdminAction::Upgrade(params) => {
+            host.upgrade(params.module).map_err(|_| CustomContractError::FailedUpgrade)?;
+            if let Some((func, parameter)) = params.migrate {
+                host.invoke_contract_raw(
+                    &ctx.self_address(),
+                    parameter.as_parameter(),
+                    func.as_entrypoint_name(),
+                    Amount::zero(),
+                )
+                .map_err(|_| CustomContractError::FailedUpgrade)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Proposes an admin action, approved by the proposing signer, and returns
+/// its id. Signers only.
+#[receive(
+    contract = "techFiestaToken",
+    name = "proposeAdminAction",
+    parameter = "AdminAction",
+    return_value = "u64",
+    error = "ContractError",
+    enable_logger,
+    mutable
+)]
+fn contract_propose_admin_action(
+    ctx: &ReceiveContext,
+    host: &mut Host<State>,
+    logger: &mut Logger,
+) -> ContractResult<u64> {
+    let signer = ensure_signer(ctx, host.state())?;
+    let action: AdminAction = ctx.parameter_cursor().get()?;
+
+    let state = host.state_mut();
+    let proposal_id = state.next_admin_proposal_id;
+    state.next_admin_proposal_id += 1;
+    let _ = state.admin_proposals.insert(proposal_id, AdminProposal {
+        action,
+        approvals: Vec::new(),
+    });
+
+    add_approval(ctx, proposal_id, signer, host, logger)?;
+    Ok(proposal_id)
+}
+
+/// Approves an admin action. The action runs as soon as the threshold of
+/// signers approved it, within this call. Signers only.
+#[receive(
+    contract = "techFiestaToken",
+    name = "approveAdminAction",
+    parameter = "u64",
+    error = "ContractError",
+    enable_logger,
+    mutable
+)]
+fn contract_approve_admin_action(
+    ctx: &ReceiveContext,
+    host: &mut Host<State>,
+    logger: &mut Logger,
+) -> ContractResult<()> {
+    let signer = ensure_signer(ctx, host.state())?;
+    let proposal_id: u64 = ctx.parameter_cursor().get()?;
+
+    add_approval(ctx, proposal_id, signer, host, logger)
+}
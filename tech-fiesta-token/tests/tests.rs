@@ -0,0 +1,447 @@
+use concordium_cis2::*;
+use concordium_smart_contract_testing::*;
+use concordium_std::Serial;
+use std::collections::BTreeMap;
+use tech_fiesta_token::*;
+
+/// The owner of the contract, which receives the preminted tokens.
+const ALICE: AccountAddress = AccountAddress([0u8; 32]);
+const ALICE_ADDR: Address = Address::Account(ALICE);
+const BOB: AccountAddress = AccountAddress([1u8; 32]);
+const BOB_ADDR: Address = Address::Account(BOB);
+const CHARLIE: AccountAddress = AccountAddress([2u8; 32]);
+/// The compliance account of the `RagnarDEX` instance used as blocklist.
+const COMPLIANCE: AccountAddress = AccountAddress([9u8; 32]);
+
+/// The initial balance of the test accounts.
+const ACC_INITIAL_BALANCE: Amount = Amount::from_ccd(10_000);
+
+/// A [`Signer`] with one set of keys, used for signing transactions.
+const SIGNER: Signer = Signer::with_one_key();
+
+const TOKEN: ContractTokenId = TokenIdU8(1);
+
+/// Test that the preminted tokens go to the owner, and can be transferred by
+/// the owner but not by others.
+#[test]
+fn test_premint_and_transfer() {
+    let (mut chain, init) = initialize();
+    assert_eq!(balance_of(&chain, &init, ALICE_ADDR), TokenAmountU64(100));
+
+    transfer(&mut chain, &init, ALICE, ALICE_ADDR, BOB_ADDR, 40).expect("Transfer succeeds");
+    assert_eq!(balance_of(&chain, &init, ALICE_ADDR), TokenAmountU64(60));
+    assert_eq!(balance_of(&chain, &init, BOB_ADDR), TokenAmountU64(40));
+
+    let error = transfer(&mut chain, &init, BOB, ALICE_ADDR, BOB_ADDR, 1)
+        .expect_err("Transfer by a non-operator fails");
+    assert_eq!(rejected_with(&error), ContractError::Unauthorized);
+}
+
+/// Test that minting stops at the maximum supply.
+#[test]
+fn test_mint_respects_max_supply() {
+    let (mut chain, init) = initialize();
+
+    mint(&mut chain, &init, 900).expect("Minting up to the maximum supply succeeds");
+    assert_eq!(balance_of(&chain, &init, BOB_ADDR), TokenAmountU64(900));
+
+    let error = mint(&mut chain, &init, 1).expect_err("Minting above the maximum supply fails");
+    assert_eq!(
+        rejected_with(&error),
+        ContractError::Custom(CustomContractError::MaxSupplyReached)
+    );
+}
+
+/// Test that `balanceOfAt` reports the balance before changes made at or after
+/// the queried time.
+#[test]
+fn test_balance_of_at() {
+    let (mut chain, init) = initialize();
+    let minted = chain.block_time();
+    chain.tick_block_time(Duration::from_seconds(10)).expect("Block time within range");
+    let transferred = chain.block_time();
+    transfer(&mut chain, &init, ALICE, ALICE_ADDR, BOB_ADDR, 40).expect("Transfer succeeds");
+    chain.tick_block_time(Duration::from_seconds(10)).expect("Block time within range");
+
+    let balances = balances_at(&chain, &init, &[
+        (ALICE_ADDR, minted),
+        (ALICE_ADDR, transferred),
+        (ALICE_ADDR, chain.block_time()),
+        (BOB_ADDR, transferred),
+        (BOB_ADDR, chain.block_time()),
+    ]);
+    assert_eq!(balances, [0, 100, 60, 0, 40].map(TokenAmountU64));
+}
+
+/// Test that `balanceOfAt` finds the right balance among many checkpoints, and
+/// that several changes within one block leave a single checkpoint.
+#[test]
+fn test_balance_of_at_many_checkpoints() {
+    let (mut chain, init) = initialize();
+    let mut times = Vec::new();
+    for _ in 0..20 {
+        chain.tick_block_time(Duration::from_seconds(1)).expect("Block time within range");
+        times.push(chain.block_time());
+        transfer(&mut chain, &init, ALICE, ALICE_ADDR, BOB_ADDR, 1).expect("Transfer succeeds");
+        transfer(&mut chain, &init, ALICE, ALICE_ADDR, BOB_ADDR, 1).expect("Transfer succeeds");
+    }
+    chain.tick_block_time(Duration::from_seconds(1)).expect("Block time within range");
+
+    let mut queries: Vec<_> = times.iter().map(|&time| (BOB_ADDR, time)).collect();
+    queries.push((BOB_ADDR, chain.block_time()));
+    let balances = balances_at(&chain, &init, &queries);
+    let expected: Vec<_> = (0..=20).map(|i| TokenAmountU64(2 * i)).collect();
+    assert_eq!(balances, expected);
+}
+
+/// Test that all aliases of an account share its balance and operators, and
+/// are authorized to transfer its tokens.
+#[test]
+fn test_aliases() {
+    let (mut chain, init) = initialize();
+    let alice_alias = ALICE.get_alias(1).expect("Alias exists");
+    chain.create_account(Account::new(alice_alias, ACC_INITIAL_BALANCE));
+    let bob_alias = BOB.get_alias(2).expect("Alias exists");
+    chain.create_account(Account::new(bob_alias, ACC_INITIAL_BALANCE));
+    assert_eq!(
+        balance_of(&chain, &init, Address::Account(alice_alias)),
+        TokenAmountU64(100)
+    );
+
+    transfer(&mut chain, &init, alice_alias, ALICE_ADDR, Address::Account(bob_alias), 10)
+        .expect("Transfer by an alias of the owner succeeds");
+    assert_eq!(balance_of(&chain, &init, ALICE_ADDR), TokenAmountU64(90));
+    assert_eq!(balance_of(&chain, &init, BOB_ADDR), TokenAmountU64(10));
+
+    // An operator added for one alias acts for every alias, under any alias.
+    let operator = |update: OperatorUpdate| {
+        UpdateOperatorParams(vec![UpdateOperator {
+            update,
+            operator: Address::Account(bob_alias),
+        }])
+    };
+    let update_operator = "techFiestaToken.updateOperator";
+    let params = operator(OperatorUpdate::Add);
+    update(&mut chain, ALICE, init.contract_address, update_operator, &params)
+        .expect("Adding an operator succeeds");
+    transfer(&mut chain, &init, BOB, Address::Account(alice_alias), BOB_ADDR, 5)
+        .expect("Transfer by an alias of the operator succeeds");
+    assert_eq!(balance_of(&chain, &init, BOB_ADDR), TokenAmountU64(15));
+
+    let params = operator(OperatorUpdate::Remove);
+    update(&mut chain, alice_alias, init.contract_address, update_operator, &params)
+        .expect("Removing the operator under an alias succeeds");
+    let error = transfer(&mut chain, &init, BOB, ALICE_ADDR, BOB_ADDR, 5)
+        .expect_err("Transfer by a removed operator fails");
+    assert_eq!(rejected_with(&error), ContractError::Unauthorized);
+}
+
+/// Test that once multisig admins are set, a mint runs as soon as the threshold
+/// of signers approved it, and that only signers approve, each once.
+#[test]
+fn test_multisig_admin_actions() {
+    let (mut chain, init) = initialize();
+    let contract = init.contract_address;
+    chain.create_account(Account::new(CHARLIE, ACC_INITIAL_BALANCE));
+    let admins = MultisigConfig {
+        signers:   vec![ALICE, BOB],
+        threshold: 2,
+    };
+
+    let error = update(&mut chain, BOB, contract, "techFiestaToken.setAdmins", &admins)
+        .expect_err("Setting admins by a non-owner fails");
+    assert_eq!(rejected_with(&error), ContractError::Unauthorized);
+    update(&mut chain, ALICE, contract, "techFiestaToken.setAdmins", &admins)
+        .expect("Setting admins succeeds");
+    let error = mint(&mut chain, &init, 10).expect_err("Minting by the owner alone fails");
+    assert_eq!(rejected_with(&error), ContractError::Unauthorized);
+
+    let action = AdminAction::Mint(MintParams {
+        owner:  BOB_ADDR,
+        tokens: tokens(10),
+    });
+    let error = update(&mut chain, CHARLIE, contract, "techFiestaToken.proposeAdminAction", &action)
+        .expect_err("Proposing by a non-signer fails");
+    assert_eq!(rejected_with(&error), ContractError::Unauthorized);
+    let invoke = update(&mut chain, ALICE, contract, "techFiestaToken.proposeAdminAction", &action)
+        .expect("Proposing succeeds");
+    let proposal_id: u64 = invoke.parse_return_value().expect("Deserialize `u64`");
+    assert_eq!(balance_of(&chain, &init, BOB_ADDR), TokenAmountU64(0));
+
+    let approve = "techFiestaToken.approveAdminAction";
+    let error = update(&mut chain, CHARLIE, contract, approve, &proposal_id)
+        .expect_err("Approving by a non-signer fails");
+    assert_eq!(rejected_with(&error), ContractError::Unauthorized);
+    let alias = ALICE.get_alias(1).expect("Alias exists");
+    chain.create_account(Account::new(alias, ACC_INITIAL_BALANCE));
+    let error = update(&mut chain, alias, contract, approve, &proposal_id)
+        .expect_err("Approving twice under an alias fails");
+    assert_eq!(
+        rejected_with(&error),
+        ContractError::Custom(CustomContractError::AlreadyApproved)
+    );
+    assert_eq!(balance_of(&chain, &init, BOB_ADDR), TokenAmountU64(0));
+
+    // The second approval reaches the threshold and mints at once.
+    update(&mut chain, BOB, contract, approve, &proposal_id).expect("Approving succeeds");
+    assert_eq!(balance_of(&chain, &init, BOB_ADDR), TokenAmountU64(10));
+    let error = update(&mut chain, BOB, contract, approve, &proposal_id)
+        .expect_err("Approving an action which already ran fails");
+    assert_eq!(
+        rejected_with(&error),
+        ContractError::Custom(CustomContractError::AdminActionNotFound)
+    );
+}
+
+/// Test that mints and transfers involving an address on the blocklist of the
+/// `RagnarDEX` instance are rejected, whether the address sends, sends from or
+/// receives the tokens.
+#[test]
+fn test_blocklist() {
+    let (mut chain, init) = initialize();
+    let params = UpdateOperatorParams(vec![UpdateOperator {
+        update:   OperatorUpdate::Add,
+        operator: BOB_ADDR,
+    }]);
+    update(&mut chain, ALICE, init.contract_address, "techFiestaToken.updateOperator", &params)
+        .expect("Adding an operator succeeds");
+    let dex = initialize_blocklist(&mut chain, &init);
+    update(&mut chain, COMPLIANCE, dex, "RagnarDEX.block", &BOB_ADDR).expect("Blocking succeeds");
+
+    let error = mint(&mut chain, &init, 1).expect_err("Minting to a blocked account fails");
+    assert_eq!(
+        rejected_with(&error),
+        ContractError::Custom(CustomContractError::Blocked)
+    );
+    let error = transfer(&mut chain, &init, ALICE, ALICE_ADDR, BOB_ADDR, 1)
+        .expect_err("Transfer to a blocked account fails");
+    assert_eq!(
+        rejected_with(&error),
+        ContractError::Custom(CustomContractError::Blocked)
+    );
+    let error = transfer(&mut chain, &init, BOB, ALICE_ADDR, ALICE_ADDR, 1)
+        .expect_err("Transfer by a blocked operator fails");
+    assert_eq!(
+        rejected_with(&error),
+        ContractError::Custom(CustomContractError::Blocked)
+    );
+
+    update(&mut chain, COMPLIANCE, dex, "RagnarDEX.unblock", &BOB_ADDR)
+        .expect("Unblocking succeeds");
+    transfer(&mut chain, &init, ALICE, ALICE_ADDR, BOB_ADDR, 1).expect("Transfer succeeds");
+    mint(&mut chain, &init, 1).expect("Minting succeeds");
+}
+
+/// Helper method for initializing the contract.
+///
+/// Does the following:
+///  - Creates the [`Chain`]
+///  - Creates two accounts, `Alice` and `Bob`, with `10_000` CCD as the
+///    initial balance.
+///  - Initializes the contract with 100 units of `TOKEN` preminted to `Alice`,
+///    out of a maximum supply of 1000.
+///  - Returns the [`Chain`] and the [`ContractInitSuccess`]
+fn initialize() -> (Chain, ContractInitSuccess) {
+    let mut chain = Chain::new();
+    chain.create_account(Account::new(ALICE, ACC_INITIAL_BALANCE));
+    chain.create_account(Account::new(BOB, ACC_INITIAL_BALANCE));
+
+    let module = module_load_v1("./concordium-out/module.wasm.v1").expect("Module exists at path");
+    let deployment = chain.module_deploy_v1(SIGNER, ALICE, module).expect("Deploy valid module");
+
+    let init = chain
+        .contract_init(SIGNER, ALICE, Energy::from(10_000), InitContractPayload {
+            amount:    Amount::zero(),
+            mod_ref:   deployment.module_reference,
+            init_name: OwnedContractName::new_unchecked("init_techFiestaToken".to_string()),
+            param:     OwnedParameter::from_serial(&InitParams {
+                premint_tokens: tokens(100),
+            })
+            .expect("Parameter within size bounds"),
+        })
+        .expect("Initializing contract");
+
+    (chain, init)
+}
+
+/// Deploys a `RagnarDEX` instance from `../concordium-out/module.wasm.v1`,
+/// makes `COMPLIANCE` its compliance account and sets it as the blocklist of
+/// the token contract.
+fn initialize_blocklist(chain: &mut Chain, init: &ContractInitSuccess) -> ContractAddress {
+    chain.create_account(Account::new(COMPLIANCE, ACC_INITIAL_BALANCE));
+    let module = module_load_v1("../concordium-out/module.wasm.v1").expect("Module exists at path");
+    let deployment = chain.module_deploy_v1(SIGNER, ALICE, module).expect("Deploy valid module");
+    // The `InitParams` of the `RagnarDEX` hold a nonzero `u16` and the
+    // collections approved from the start, here none.
+    let dex = chain
+        .contract_init(SIGNER, ALICE, Energy::from(10_000), InitContractPayload {
+            amount:    Amount::zero(),
+            mod_ref:   deployment.module_reference,
+            init_name: OwnedContractName::new_unchecked("init_RagnarDEX".to_string()),
+            param:     OwnedParameter::from_serial(&(1u16, Vec::<()>::new()))
+                .expect("Parameter within size bounds"),
+        })
+        .expect("Initializing the Dex")
+        .contract_address;
+
+    // Setting the compliance account waits for the admin delay of the Dex.
+    update(chain, ALICE, dex, "RagnarDEX.setCompliance", &Some(COMPLIANCE))
+        .expect("Queueing the compliance account succeeds");
+    chain.tick_block_time(Duration::from_days(2)).expect("Block time within range");
+    update(chain, ALICE, dex, "RagnarDEX.executeAdminAction", &0u64)
+        .expect("Setting the compliance account succeeds");
+
+    update(chain, ALICE, init.contract_address, "techFiestaToken.setBlocklist", &Some(dex))
+        .expect("Setting the blocklist succeeds");
+    dex
+}
+
+/// `amount` units of `TOKEN`, with a maximum supply of 1000.
+fn tokens(amount: u64) -> BTreeMap<ContractTokenId, (MetadataUrl, TokenParams)> {
+    let metadata_url = MetadataUrl {
+        url:  "https://example.com/token.json".to_string(),
+        hash: None,
+    };
+    [(TOKEN, (metadata_url, TokenParams {
+        amount:     TokenAmountU64(amount),
+        max_supply: TokenAmountU64(1_000),
+    }))]
+    .into()
+}
+
+/// Mints `amount` units of `TOKEN` to `Bob`, as the owner.
+fn mint(
+    chain: &mut Chain,
+    init: &ContractInitSuccess,
+    amount: u64,
+) -> Result<ContractInvokeSuccess, ContractInvokeError> {
+    chain.contract_update(SIGNER, ALICE, ALICE_ADDR, Energy::from(10_000), UpdateContractPayload {
+        address:      init.contract_address,
+        amount:       Amount::zero(),
+        receive_name: OwnedReceiveName::new_unchecked("techFiestaToken.mint".to_string()),
+        message:      OwnedParameter::from_serial(&MintParams {
+            owner:  BOB_ADDR,
+            tokens: tokens(amount),
+        })
+        .expect("Parameter within size bounds"),
+    })
+}
+
+/// Transfers `amount` units of `TOKEN` from `from` to the account `to`, sent
+/// by `sender`.
+fn transfer(
+    chain: &mut Chain,
+    init: &ContractInitSuccess,
+    sender: AccountAddress,
+    from: Address,
+    to: Address,
+    amount: u64,
+) -> Result<ContractInvokeSuccess, ContractInvokeError> {
+    let to = match to {
+        Address::Account(account) => Receiver::Account(account),
+        Address::Contract(_) => panic!("Transfers to contracts are not used in these tests"),
+    };
+    let params = TransferParams::from(vec![concordium_cis2::Transfer {
+        token_id: TOKEN,
+        amount: TokenAmountU64(amount),
+        from,
+        to,
+        data: AdditionalData::empty(),
+    }]);
+    chain.contract_update(
+        SIGNER,
+        sender,
+        Address::Account(sender),
+        Energy::from(10_000),
+        UpdateContractPayload {
+            address:      init.contract_address,
+            amount:       Amount::zero(),
+            receive_name: OwnedReceiveName::new_unchecked("techFiestaToken.transfer".to_string()),
+            message:      OwnedParameter::from_serial(&params)
+                .expect("Parameter within size bounds"),
+        },
+    )
+}
+
+/// Calls `receive_name` of the contract at `address` with `params`, sent by
+/// `sender`.
+fn update<P: Serial>(
+    chain: &mut Chain,
+    sender: AccountAddress,
+    address: ContractAddress,
+    receive_name: &str,
+    params: &P,
+) -> Result<ContractInvokeSuccess, ContractInvokeError> {
+    chain.contract_update(
+        SIGNER,
+        sender,
+        Address::Account(sender),
+        Energy::from(10_000),
+        UpdateContractPayload {
+            address,
+            amount: Amount::zero(),
+            receive_name: OwnedReceiveName::new_unchecked(receive_name.to_string()),
+            message: OwnedParameter::from_serial(params).expect("Parameter within size bounds"),
+        },
+    )
+}
+
+/// The current balance of `TOKEN` of `address`.
+fn balance_of(chain: &Chain, init: &ContractInitSuccess, address: Address) -> ContractTokenAmount {
+    let params = ContractBalanceOfQueryParams {
+        queries: vec![BalanceOfQuery {
+            token_id: TOKEN,
+            address,
+        }],
+    };
+    let invoke = chain
+        .contract_invoke(ALICE, ALICE_ADDR, Energy::from(10_000), UpdateContractPayload {
+            address:      init.contract_address,
+            amount:       Amount::zero(),
+            receive_name: OwnedReceiveName::new_unchecked("techFiestaToken.balanceOf".to_string()),
+            message:      OwnedParameter::from_serial(&params)
+                .expect("Parameter within size bounds"),
+        })
+        .expect("Invoking `balanceOf` succeeds");
+    let response: ContractBalanceOfQueryResponse =
+        invoke.parse_return_value().expect("Deserialize `BalanceOfQueryResponse`");
+    response.0[0]
+}
+
+/// The balances of `TOKEN` of each address just before the given time.
+fn balances_at(
+    chain: &Chain,
+    init: &ContractInitSuccess,
+    queries: &[(Address, Timestamp)],
+) -> Vec<ContractTokenAmount> {
+    let params = BalanceOfAtQueryParams {
+        queries: queries
+            .iter()
+            .map(|&(address, time)| BalanceOfAtQuery {
+                token_id: TOKEN,
+                address,
+                time,
+            })
+            .collect(),
+    };
+    let invoke = chain
+        .contract_invoke(ALICE, ALICE_ADDR, Energy::from(10_000), UpdateContractPayload {
+            address:      init.contract_address,
+            amount:       Amount::zero(),
+            receive_name: OwnedReceiveName::new_unchecked(
+                "techFiestaToken.balanceOfAt".to_string(),
+            ),
+            message:      OwnedParameter::from_serial(&params)
+                .expect("Parameter within size bounds"),
+        })
+        .expect("Invoking `balanceOfAt` succeeds");
+    let response: ContractBalanceOfQueryResponse =
+        invoke.parse_return_value().expect("Deserialize `BalanceOfQueryResponse`");
+    response.0
+}
+
+/// The error a failed update was rejected with.
+fn rejected_with(error: &ContractInvokeError) -> ContractError {
+    let return_value = error.return_value().expect("Rejected with a return value");
+    from_bytes(return_value).expect("Deserialize `ContractError`")
+}
@@ -1,83 +1,535 @@
+use concordium_cis2::{
+    BalanceOfQuery, BalanceOfQueryParams, BalanceOfQueryResponse, MetadataUrl, OperatorUpdate,
+    Receiver, TokenAmountU256, TokenAmountU64, TokenIdU8, TokenIdVec, UpdateOperator,
+    UpdateOperatorParams,
+};
 use concordium_smart_contract_testing::*;
-use id_concordium::*;
+use concordium_std::{CredentialSignatures, HashSha2256, Serial, SignatureEd25519};
+use id_concordium::{
+    errors::DexError,
+    parameter::*,
+    state::{CollectionInfo, Currency, PriceMode},
+};
+use mocks::{CallbackResult, HostileReceiverParams, SetPriceParams};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 
 /// A test account.
 const ALICE: AccountAddress = AccountAddress([0u8; 32]);
 const ALICE_ADDR: Address = Address::Account(ALICE);
 
+/// An account selling tokens through signed orders.
+const MAKER: AccountAddress = AccountAddress([1u8; 32]);
+const MAKER_ADDR: Address = Address::Account(MAKER);
+
+/// An account buying tokens.
+const BUYER: AccountAddress = AccountAddress([2u8; 32]);
+const BUYER_ADDR: Address = Address::Account(BUYER);
+
 /// The initial balance of the ALICE test account.
 const ACC_INITIAL_BALANCE: Amount = Amount::from_ccd(10_000);
 
 /// A [`Signer`] with one set of keys, used for signing transactions.
 const SIGNER: Signer = Signer::with_one_key();
 
-/// Test that invoking the `receive` endpoint with the `false` parameter
-/// succeeds in updating the contract.
+/// Test that a freshly initialized Dex has no listings.
 #[test]
-fn test_throw_no_error() {
-    let (mut chain, init) = initialize();
+fn test_init_has_no_listings() {
+    let (chain, init) = initialize();
 
-    // Update the contract via the `receive` entrypoint with the parameter `false`.
-    chain
-        .contract_update(SIGNER, ALICE, ALICE_ADDR, Energy::from(10_000), UpdateContractPayload {
-            address:      init.contract_address,
-            amount:       Amount::zero(),
-            receive_name: OwnedReceiveName::new_unchecked("id_concordium.receive".to_string()),
-            message:      OwnedParameter::from_serial(&false)
-                .expect("Parameter within size bounds"),
-        })
-        .expect("Update succeeds with `false` as input.");
+    assert!(listings(&chain, init.contract_address).0.is_empty());
 }
 
-/// Test that invoking the `receive` endpoint with the `true` parameter
-/// results in the `CustomError` being thrown.
+/// Test that the Dex cannot be initialized with a zero amount.
 #[test]
-fn test_throw_error() {
-    let (mut chain, init) = initialize();
+fn test_init_rejects_zero_amount() {
+    let mut chain = Chain::new();
+    chain.create_account(Account::new(ALICE, ACC_INITIAL_BALANCE));
+    let module = module_load_v1("./concordium-out/module.wasm.v1").expect("Module exists at path");
+    let deployment = chain.module_deploy_v1(SIGNER, ALICE, module).expect("Deploy valid module");
 
-    // Update the contract via the `receive` entrypoint with the parameter `true`.
-    let update = chain
-        .contract_update(SIGNER, ALICE, ALICE_ADDR, Energy::from(10_000), UpdateContractPayload {
-            address:      init.contract_address,
-            amount:       Amount::zero(),
-            receive_name: OwnedReceiveName::new_unchecked("id_concordium.receive".to_string()),
-            message:      OwnedParameter::from_serial(&true).expect("Parameter within size bounds"),
+    let init = chain
+        .contract_init(SIGNER, ALICE, Energy::from(10_000), InitContractPayload {
+            amount:    Amount::zero(),
+            mod_ref:   deployment.module_reference,
+            init_name: OwnedContractName::new_unchecked("init_RagnarDEX".to_string()),
+            param:     OwnedParameter::from_serial(&InitParams {
+                amount:      0,
+                collections: Vec::new(),
+            })
+            .expect("Parameter within size bounds"),
         })
-        .expect_err("Update fails with `true` as input.");
+        .expect_err("Initializing with a zero amount fails");
+
+    assert!(matches!(init.kind, ContractInitErrorKind::ExecutionError {
+        error: InitExecutionError::Reject { .. },
+        ..
+    }));
+}
+
+/// Test that an order signed with a wallet can be filled, and that the maker
+/// is paid and the buyer credited with their overpayment. A signature over the
+/// order without the wallet prefix is rejected.
+#[test]
+fn test_fill_signed_order() {
+    let mut chain = new_chain();
+    let keys = AccountKeys::singleton(&mut rand::thread_rng());
+    chain.create_account(Account::new_with_keys(
+        MAKER,
+        AccountBalance::new(ACC_INITIAL_BALANCE, Amount::zero(), Amount::zero())
+            .expect("Valid balance"),
+        (&keys).into(),
+    ));
+    chain.create_account(Account::new(BUYER, ACC_INITIAL_BALANCE));
+    let (dex, token) = initialize_token(&mut chain);
 
-    // Check that the contract returned `CustomError`.
-    let error: Error = update.parse_return_value().expect("Deserialize `Error`");
-    assert_eq!(error, Error::CustomError);
+    let order = Order {
+        maker:                MAKER,
+        cis_contract_address: token,
+        token_id:             TokenIdVec(vec![1]),
+        price:                Amount::from_ccd(5),
+        quantity:             TokenAmountU256(10u64.into()),
+        expiry:               Timestamp::from_timestamp_millis(u64::MAX),
+        nonce:                0,
+    };
+    let mut message = to_bytes(&dex);
+    message.extend(to_bytes(&order));
+
+    let unprefixed = sign(&keys, &Sha256::digest(&message));
+    let two = TokenAmountU256(2u64.into());
+    let error = fill_order(&mut chain, dex, &order, unprefixed, two, Amount::from_ccd(12))
+        .expect_err("Filling an order signed without the wallet prefix fails");
+    assert_eq!(error.return_value(), Some(&to_bytes(&DexError::InvalidSignature)[..]));
+
+    let signatures = sign(&keys, &wallet_message_hash(MAKER, &message));
+    fill_order(&mut chain, dex, &order, signatures, two, Amount::from_ccd(12))
+        .expect("Filling a signed order succeeds");
+    // A quantity which overflows the filled quantity is rejected.
+    let max = TokenAmountU256([0xff; 32].into());
+    let signatures = sign(&keys, &wallet_message_hash(MAKER, &message));
+    let error = fill_order(&mut chain, dex, &order, signatures, max, Amount::from_ccd(12))
+        .expect_err("Filling an overflowing quantity fails");
+    assert_eq!(error.return_value(), Some(&to_bytes(&DexError::InvalidTokenQuantity)[..]));
+
+    assert_eq!(token_balance(&chain, token, BUYER_ADDR), TokenAmountU64(2));
+    assert_eq!(token_balance(&chain, token, MAKER_ADDR), TokenAmountU64(8));
+    assert_eq!(withdraw(&mut chain, dex, MAKER), [(dex, Amount::from_ccd(10), MAKER)]);
+    assert_eq!(withdraw(&mut chain, dex, BUYER), [(dex, Amount::from_ccd(2), BUYER)]);
+
+    let invoke = update(&mut chain, ALICE, dex, "RagnarDEX.orderHash", &order, Amount::zero())
+        .expect("Invoking `orderHash` succeeds");
+    let hash: HashSha2256 = invoke.parse_return_value().expect("Deserialize `HashSha2256`");
+    assert_eq!(hash.0, <[u8; 32]>::from(Sha256::digest(&message)));
+}
+
+/// Test that a contract receiving a bought token cannot buy again from within
+/// `onReceivingCIS2`: the nested `transfer` is rejected with `Reentrancy` while
+/// the outer purchase goes through.
+#[test]
+fn test_transfer_rejects_reentrant_receiver() {
+    let mut chain = new_chain();
+    chain.create_account(Account::new(MAKER, ACC_INITIAL_BALANCE));
+    chain.create_account(Account::new(BUYER, ACC_INITIAL_BALANCE));
+    let (dex, token) = initialize_token(&mut chain);
+    update(&mut chain, ALICE, dex, "RagnarDEX.setContractPrincipals", &true, Amount::zero())
+        .expect("Queueing the change succeeds");
+    chain.tick_block_time(Duration::from_days(2)).expect("Block time within range");
+    update(&mut chain, ALICE, dex, "RagnarDEX.executeAdminAction", &0u64, Amount::zero())
+        .expect("Allowing contract principals succeeds");
+
+    list(&mut chain, dex, token, PriceMode::Fixed);
+
+    let receiver = initialize_mock(&mut chain, "init_HostileReceiver", &HostileReceiverParams {
+        target:     dex,
+        entrypoint: OwnedEntrypointName::new_unchecked("transfer".to_string()),
+        parameter:  OwnedParameter::from_serial(&purchase(token, Receiver::Account(BUYER)))
+            .expect("Parameter within size bounds"),
+    });
+
+    let to = Receiver::Contract(
+        receiver,
+        OwnedEntrypointName::new_unchecked("onReceivingCIS2".to_string()),
+    );
+    update(&mut chain, BUYER, dex, "RagnarDEX.transfer", &purchase(token, to), Amount::from_ccd(5))
+        .expect("Buying for the receiver succeeds");
+
+    assert_eq!(token_balance(&chain, token, Address::Contract(receiver)), TokenAmountU64(1));
+    assert_eq!(token_balance(&chain, token, BUYER_ADDR), TokenAmountU64(0));
+    let invoke = update(&mut chain, ALICE, receiver, "HostileReceiver.result", &(), Amount::zero())
+        .expect("Invoking `result` succeeds");
+    let result: Option<CallbackResult> =
+        invoke.parse_return_value().expect("Deserialize `CallbackResult`");
+    assert!(matches!(
+        result,
+        Some(CallbackResult::Rejected { return_value, .. })
+            if return_value == to_bytes(&DexError::Reentrancy)
+    ));
+}
+
+/// Test that oracle listings are sold at the adjusted price of the feed, and
+/// that purchases are rejected while the feed is older than the maximum age.
+#[test]
+fn test_transfer_oracle_price() {
+    let mut chain = new_chain();
+    chain.create_account(Account::new(MAKER, ACC_INITIAL_BALANCE));
+    chain.create_account(Account::new(BUYER, ACC_INITIAL_BALANCE));
+    let (dex, token) = initialize_token(&mut chain);
+    let oracle = initialize_mock(&mut chain, "init_MockOracle", &());
+    let set_price = |chain: &mut Chain, price| {
+        let params = SetPriceParams {
+            feed_id: 7,
+            price,
+        };
+        update(chain, ALICE, oracle, "MockOracle.setPrice", &params, Amount::zero())
+            .expect("Setting the price succeeds");
+    };
+    set_price(&mut chain, Amount::from_ccd(2));
+
+    list(&mut chain, dex, token, PriceMode::Oracle {
+        oracle,
+        feed_id: 7,
+        adjustment_basis_points: 1_000,
+        max_age: Duration::from_hours(1),
+    });
+
+    let buy = |chain: &mut Chain, amount| {
+        let params = purchase(token, Receiver::Account(BUYER));
+        update(chain, BUYER, dex, "RagnarDEX.transfer", &params, amount)
+    };
+    let error = buy(&mut chain, Amount::from_micro_ccd(2_199_999))
+        .expect_err("Paying less than the adjusted feed price fails");
+    assert_eq!(error.return_value(), Some(&to_bytes(&DexError::InvalidAmountPaid)[..]));
+    buy(&mut chain, Amount::from_micro_ccd(2_200_000))
+        .expect("Buying at the adjusted feed price succeeds");
+
+    chain.tick_block_time(Duration::from_hours(2)).expect("Block time within range");
+    let error = buy(&mut chain, Amount::from_ccd(10)).expect_err("Buying at a stale price fails");
+    assert_eq!(error.return_value(), Some(&to_bytes(&DexError::StalePrice)[..]));
+
+    set_price(&mut chain, Amount::from_ccd(3));
+    buy(&mut chain, Amount::from_micro_ccd(3_300_000))
+        .expect("Buying after the feed is updated succeeds");
+
+    assert_eq!(token_balance(&chain, token, BUYER_ADDR), TokenAmountU64(2));
+    assert_eq!(withdraw(&mut chain, dex, MAKER), [(
+        dex,
+        Amount::from_micro_ccd(5_500_000),
+        MAKER
+    )]);
+}
+
+/// Test that an alias of a seller shares the listing of the seller: listing the
+/// token again under the alias keeps the existing listing, buyers find the
+/// listing under the alias, and the alias withdraws the proceeds.
+#[test]
+fn test_seller_alias_shares_listing() {
+    let mut chain = new_chain();
+    chain.create_account(Account::new(MAKER, ACC_INITIAL_BALANCE));
+    chain.create_account(Account::new(BUYER, ACC_INITIAL_BALANCE));
+    let (dex, token) = initialize_token(&mut chain);
+    list(&mut chain, dex, token, PriceMode::Fixed);
+
+    let alias = MAKER.get_alias(1).expect("Alias exists");
+    chain.create_account(Account::new(alias, ACC_INITIAL_BALANCE));
+    let params = AddParams {
+        cis_contract_address: token,
+        token_id:             TokenIdVec(vec![1]),
+        price:                Amount::from_ccd(7),
+        currency:             Currency::Ccd,
+        mode:                 PriceMode::Fixed,
+        quantity:             TokenAmountU256(3u64.into()),
+        expires_at:           None,
+        attestation:          None,
+    };
+    update(&mut chain, alias, dex, "RagnarDEX.add", &params, Amount::zero())
+        .expect("Listing again under an alias succeeds");
+    let list = listings(&chain, dex);
+    assert_eq!(list.0.len(), 1);
+    assert!(matches!(list.0[0].owner, Address::Account(owner) if owner.is_alias(&MAKER)));
+    assert_eq!(list.0[0].price, Amount::from_ccd(5));
+    assert_eq!(list.0[0].quantity, TokenAmountU256(10u64.into()));
+
+    let params = TransferParams {
+        owner: Address::Account(alias),
+        ..purchase(token, Receiver::Account(BUYER))
+    };
+    update(&mut chain, BUYER, dex, "RagnarDEX.transfer", &params, Amount::from_ccd(5))
+        .expect("Buying from the alias succeeds");
+    assert_eq!(token_balance(&chain, token, BUYER_ADDR), TokenAmountU64(1));
+    assert_eq!(withdraw(&mut chain, dex, alias), [(dex, Amount::from_ccd(5), alias)]);
+}
+
+/// Helper method for creating the [`Chain`] with one account, `Alice`, with
+/// `10_000` CCD as the initial balance.
+fn new_chain() -> Chain {
+    let mut chain = Chain::new();
+    chain.create_account(Account::new(ALICE, ACC_INITIAL_BALANCE));
+    chain
 }
 
 /// Helper method for initializing the contract.
 ///
 /// Does the following:
-///  - Creates the [`Chain`]
-///  - Creates one account, `Alice` with `10_000` CCD as the initial balance.
-///  - Initializes the contract.
+///  - Creates the [`Chain`] with `Alice`.
+///  - Initializes the contract without collections.
 ///  - Returns the [`Chain`] and the [`ContractInitSuccess`]
 fn initialize() -> (Chain, ContractInitSuccess) {
-    // Initialize the test chain.
-    let mut chain = Chain::new();
-
-    // Create the test account.
-    chain.create_account(Account::new(ALICE, ACC_INITIAL_BALANCE));
+    let mut chain = new_chain();
+    let init = initialize_dex(&mut chain, Vec::new());
+    (chain, init)
+}
 
+/// Helper method for deploying and initializing the contract with the given
+/// collections approved from the start.
+fn initialize_dex(
+    chain: &mut Chain,
+    collections: Vec<RegisterCollectionParams>,
+) -> ContractInitSuccess {
     // Load the module.
     let module = module_load_v1("./concordium-out/module.wasm.v1").expect("Module exists at path");
     // Deploy the module.
     let deployment = chain.module_deploy_v1(SIGNER, ALICE, module).expect("Deploy valid module");
 
     // Initialize the contract.
-    let init = chain
+    chain
         .contract_init(SIGNER, ALICE, Energy::from(10_000), InitContractPayload {
             amount:    Amount::zero(),
             mod_ref:   deployment.module_reference,
-            init_name: OwnedContractName::new_unchecked("init_id_concordium".to_string()),
-            param:     OwnedParameter::empty(),
+            init_name: OwnedContractName::new_unchecked("init_RagnarDEX".to_string()),
+            param:     OwnedParameter::from_serial(&InitParams {
+                amount: 1,
+                collections,
+            })
+            .expect("Parameter within size bounds"),
         })
-        .expect("Initializing contract");
+        .expect("Initializing contract")
+}
 
-    (chain, init)
+/// Helper method for setting up a `techFiestaToken` traded on the Dex.
+///
+/// Does the following:
+///  - Initializes the token with 10 units of token 1 preminted to `Maker`.
+///  - Initializes the Dex with the token as a collection, so no admin delay
+///    is waited out.
+///  - Makes the Dex an operator of `Maker`.
+///  - Returns the addresses of the Dex and the token.
+fn initialize_token(chain: &mut Chain) -> (ContractAddress, ContractAddress) {
+    let module = module_load_v1("./tech-fiesta-token/concordium-out/module.wasm.v1")
+        .expect("Module exists at path");
+    let deployment = chain.module_deploy_v1(SIGNER, ALICE, module).expect("Deploy valid module");
+    let metadata_url = MetadataUrl {
+        url:  "https://example.com/token.json".to_string(),
+        hash: None,
+    };
+    let token_params = tech_fiesta_token::TokenParams {
+        amount:     TokenAmountU64(10),
+        max_supply: TokenAmountU64(10),
+    };
+    let premint_tokens = BTreeMap::from([(TokenIdU8(1), (metadata_url, token_params))]);
+    let token = chain
+        .contract_init(SIGNER, MAKER, Energy::from(10_000), InitContractPayload {
+            amount:    Amount::zero(),
+            mod_ref:   deployment.module_reference,
+            init_name: OwnedContractName::new_unchecked("init_techFiestaToken".to_string()),
+            param:     OwnedParameter::from_serial(&tech_fiesta_token::InitParams {
+                premint_tokens,
+            })
+            .expect("Parameter within size bounds"),
+        })
+        .expect("Initializing token")
+        .contract_address;
+
+    let collection = RegisterCollectionParams {
+        cis_contract_address: token,
+        info:                 CollectionInfo {
+            name:     None,
+            symbol:   None,
+            decimals: None,
+            owner:    Some(MAKER),
+        },
+    };
+    let dex = initialize_dex(chain, vec![collection]).contract_address;
+
+    let params = UpdateOperatorParams(vec![UpdateOperator {
+        update:   OperatorUpdate::Add,
+        operator: Address::Contract(dex),
+    }]);
+    update(chain, MAKER, token, "techFiestaToken.updateOperator", &params, Amount::zero())
+        .expect("Adding the Dex as operator succeeds");
+
+    (dex, token)
+}
+
+/// Lists 10 units of token 1 of `Maker` on the Dex, priced by `mode` from a
+/// listed price of 5 CCD.
+fn list(chain: &mut Chain, dex: ContractAddress, token: ContractAddress, mode: PriceMode) {
+    let params = AddParams {
+        cis_contract_address: token,
+        token_id:             TokenIdVec(vec![1]),
+        price:                Amount::from_ccd(5),
+        currency:             Currency::Ccd,
+        mode,
+        quantity:             TokenAmountU256(10u64.into()),
+        expires_at:           None,
+        attestation:          None,
+    };
+    update(chain, MAKER, dex, "RagnarDEX.add", &params, Amount::zero())
+        .expect("Listing the token succeeds");
+}
+
+/// The listings of the Dex.
+fn listings(chain: &Chain, dex: ContractAddress) -> TokenList {
+    let invoke = chain
+        .contract_invoke(ALICE, ALICE_ADDR, Energy::from(10_000), UpdateContractPayload {
+            address:      dex,
+            amount:       Amount::zero(),
+            receive_name: OwnedReceiveName::new_unchecked("RagnarDEX.list".to_string()),
+            message:      OwnedParameter::empty(),
+        })
+        .expect("Invoking `list` succeeds");
+    invoke.parse_return_value().expect("Deserialize `TokenList`")
+}
+
+/// Parameters for buying one unit of the listing of `Maker` for `to`.
+fn purchase(token: ContractAddress, to: Receiver) -> TransferParams {
+    TransferParams {
+        cis_contract_address: token,
+        token_id:             TokenIdVec(vec![1]),
+        to,
+        owner:                MAKER_ADDR,
+        quantity:             TokenAmountU256(1u64.into()),
+        referrer:             None,
+        attestation:          None,
+    }
+}
+
+/// Deploys the module of `mocks/` and initializes the contract of `init_name`
+/// with `params`, returning its address.
+fn initialize_mock<P: Serial>(chain: &mut Chain, init_name: &str, params: &P) -> ContractAddress {
+    let module =
+        module_load_v1("./mocks/concordium-out/module.wasm.v1").expect("Module exists at path");
+    let deployment = chain.module_deploy_v1(SIGNER, ALICE, module).expect("Deploy valid module");
+    chain
+        .contract_init(SIGNER, ALICE, Energy::from(10_000), InitContractPayload {
+            amount:    Amount::zero(),
+            mod_ref:   deployment.module_reference,
+            init_name: OwnedContractName::new_unchecked(init_name.to_string()),
+            param:     OwnedParameter::from_serial(params).expect("Parameter within size bounds"),
+        })
+        .expect("Initializing mock")
+        .contract_address
+}
+
+/// The hash a wallet signs for `message`: the message prefixed with the
+/// address of the signer and eight zero bytes.
+fn wallet_message_hash(signer: AccountAddress, message: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(signer.0);
+    hasher.update([0u8; 8]);
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+/// Signs `data` with every key of `keys`.
+fn sign(keys: &AccountKeys, data: &[u8]) -> concordium_std::AccountSignatures {
+    let sigs = keys
+        .sign_data(data)
+        .into_iter()
+        .map(|(credential, signatures)| {
+            let sigs = signatures
+                .into_iter()
+                .map(|(key, signature)| {
+                    let signature = signature.sig.try_into().expect("Ed25519 signature");
+                    (key.0, concordium_std::Signature::Ed25519(SignatureEd25519(signature)))
+                })
+                .collect();
+            (credential.index, CredentialSignatures {
+                sigs,
+            })
+        })
+        .collect();
+    concordium_std::AccountSignatures {
+        sigs,
+    }
+}
+
+/// Fills `quantity` units of `order` for `Buyer`, paying `amount`.
+fn fill_order(
+    chain: &mut Chain,
+    dex: ContractAddress,
+    order: &Order,
+    signatures: concordium_std::AccountSignatures,
+    quantity: TokenAmountU256,
+    amount: Amount,
+) -> Result<ContractInvokeSuccess, ContractInvokeError> {
+    let params = FillOrderParams {
+        order:             Order {
+            token_id: order.token_id.clone(),
+            ..*order
+        },
+        signatures,
+        to:                Receiver::Account(BUYER),
+        quantity,
+        attestation:       None,
+        maker_attestation: None,
+    };
+    update(chain, BUYER, dex, "RagnarDEX.fillOrder", &params, amount)
+}
+
+/// Withdraws the proceeds of `account` from the Dex, and returns the transfers
+/// made.
+fn withdraw(
+    chain: &mut Chain,
+    dex: ContractAddress,
+    account: AccountAddress,
+) -> Vec<(ContractAddress, Amount, AccountAddress)> {
+    update(chain, account, dex, "RagnarDEX.withdraw", &(), Amount::zero())
+        .expect("Withdrawing succeeds")
+        .account_transfers()
+        .collect()
+}
+
+/// The balance of token 1 of `address`.
+fn token_balance(chain: &Chain, token: ContractAddress, address: Address) -> TokenAmountU64 {
+    let params = BalanceOfQueryParams {
+        queries: vec![BalanceOfQuery {
+            token_id: TokenIdU8(1),
+            address,
+        }],
+    };
+    let invoke = chain
+        .contract_invoke(ALICE, ALICE_ADDR, Energy::from(10_000), UpdateContractPayload {
+            address:      token,
+            amount:       Amount::zero(),
+            receive_name: OwnedReceiveName::new_unchecked("techFiestaToken.balanceOf".to_string()),
+            message:      OwnedParameter::from_serial(&params)
+                .expect("Parameter within size bounds"),
+        })
+        .expect("Invoking `balanceOf` succeeds");
+    let response: BalanceOfQueryResponse<TokenAmountU64> =
+        invoke.parse_return_value().expect("Deserialize `BalanceOfQueryResponse`");
+    response.0[0]
+}
+
+/// Sends an update of `receive_name` of `contract` from `sender`.
+fn update<P: Serial>(
+    chain: &mut Chain,
+    sender: AccountAddress,
+    contract: ContractAddress,
+    receive_name: &str,
+    params: &P,
+    amount: Amount,
+) -> Result<ContractInvokeSuccess, ContractInvokeError> {
+    chain.contract_update(
+        SIGNER,
+        sender,
+        Address::Account(sender),
+        Energy::from(100_000),
+        UpdateContractPayload {
+            address: contract,
+            amount,
+            receive_name: OwnedReceiveName::new_unchecked(receive_name.to_string()),
+            message: OwnedParameter::from_serial(params).expect("Parameter within size bounds"),
+        },
+    )
 }